@@ -5,9 +5,11 @@ use std::{fs::File, path::Path};
 
 use crate::{
     config::{self, ClientConfig, Config, UserConfig},
-    db::{self, AmsInfo, TaxDb},
+    db::{storage, AmsInfo},
     error::{self, Error, UserErrorKind},
-    format::printer::{FdfPrinter, JsonPrinter, PdfPrinter, Printer, XfdfPrinter},
+    format::printer::{
+        FdfPrinter, JsonPrinter, NativePdfPrinter, PdfBackend, PdftkPrinter, Printer, XfdfPrinter,
+    },
     format::OutputFormat,
     forms::amsform::{self, FormField},
     taxcalculator,
@@ -38,8 +40,18 @@ pub struct AmsArgs {
     deduction_percentage: Decimal,
     #[clap(long, help = "Output format (PDF, FDF, XFDF, JSON)", default_value_t = OutputFormat::Pdf)]
     output_format: OutputFormat,
+    #[clap(
+        long,
+        help = "Font for PDF output text fields (path to TTF file, pdftk backend also accepts font names). Overrides pdf.replacementFont from configuration"
+    )]
+    font: Option<String>,
     #[clap(long, help = "Path to config file with user specific settings")]
     user_config: Option<String>,
+    #[clap(
+        long,
+        help = "Taxpayer profile id or JMBG. Fills in the taxpayer's details and tags the DB entry with them"
+    )]
+    taxpayer: Option<String>,
     #[clap(long, help = "Path to config file with client specific settings")]
     client_config: Option<String>,
     #[clap(
@@ -57,6 +69,17 @@ pub struct AmsArgs {
 }
 
 pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
+    if args.font.is_some() && args.output_format != OutputFormat::Pdf {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "--font can only be used with PDF output format, not {}",
+            args.output_format
+        ))));
+    }
+    let taxpayer = args
+        .taxpayer
+        .as_deref()
+        .map(|taxpayer| config.taxpayer(taxpayer))
+        .transpose()?;
     if !Path::new(config.ams.cache_location.as_str()).exists() {
         println!(
             "Cached AMS form not found at: {}\nResorting to download from: {}",
@@ -76,14 +99,26 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
     let fdf_printer = FdfPrinter {};
     let xfdf_printer = XfdfPrinter {};
     let json_printer = JsonPrinter::default();
-    let pdf_printer = PdfPrinter {
+    let font = args
+        .font
+        .clone()
+        .or_else(|| config.pdf.replacement_font.clone());
+    let native_pdf_printer = NativePdfPrinter {
+        source_pdf: config.ams.cache_location.clone(),
+        font: font.clone(),
+    };
+    let pdftk_printer = PdftkPrinter {
         config: &config,
         source_pdf: config.ams.cache_location.clone(),
         xfdf_printer: &xfdf_printer,
+        font,
     };
 
     let printer: &dyn Printer = match args.output_format {
-        OutputFormat::Pdf => &pdf_printer,
+        OutputFormat::Pdf => match config.pdf.backend {
+            PdfBackend::Native => &native_pdf_printer,
+            PdfBackend::Pdftk => &pdftk_printer,
+        },
         OutputFormat::Fdf => &fdf_printer,
         OutputFormat::Xfdf => &xfdf_printer,
         OutputFormat::Json => &json_printer,
@@ -99,15 +134,18 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
         args.deduction_percentage.round_dp(2),
     );
 
-    let user_config = match &args.user_config {
-        Some(path) => config::parse_config::<UserConfig>(path.as_str())?,
-        None => config
-            .user
-            .clone()
-            .ok_or(Error::UserError(UserErrorKind::MissingConfig(
-                "user configuration".to_string(),
-                "--user-config".to_string(),
-            )))?,
+    let user_config = match (&args.user_config, &taxpayer) {
+        (Some(path), _) => config::parse_config::<UserConfig>(path.as_str())?,
+        (None, Some((_, user))) => user.clone(),
+        (None, None) => {
+            config
+                .user
+                .clone()
+                .ok_or(Error::UserError(UserErrorKind::MissingConfig(
+                    "user configuration".to_string(),
+                    "--user-config".to_string(),
+                )))?
+        }
     };
     form.fill_main_field(FormField::UserName, user_config.name)?;
     form.fill_main_field(FormField::UserAddress, user_config.address)?;
@@ -126,7 +164,7 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
         }
     }
 
-    let client_config = match &args.user_config {
+    let client_config = match &args.client_config {
         Some(path) => config::parse_config::<ClientConfig>(path.as_str())?,
         None => config
             .client
@@ -136,7 +174,7 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
                 "--client-config".to_string(),
             )))?,
     };
-    form.fill_main_field(FormField::CompanyName, client_config.name)?;
+    form.fill_main_field(FormField::CompanyName, client_config.name.clone())?;
     form.fill_main_field(FormField::CompanyAddress, client_config.address)?;
     form.fill_main_field(FormField::CompanyCountry, client_config.country)?;
 
@@ -153,11 +191,19 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
                 "Output location seems to be invalid!".to_string(),
             )))?;
 
-    printer.write_to_file(form.to_dict()?, output_file_path_str)?;
+    printer.write_form_to_file(&mut form, output_file_path_str)?;
     println!("Saved AMS form to: {}", output_file_path_str);
 
     if !args.skip_db {
         if let Some(invoice_date) = &args.invoice_date {
+            let ams_info = AmsInfo {
+                income: Some(args.income.round_dp(2)),
+                deduction_percentage: Some(args.deduction_percentage.round_dp(2)),
+                client: Some(client_config.name),
+                taxpayer: taxpayer.map(|(id, _)| id),
+                form_path: Some(output_file_path_str.to_string()),
+                ..ams_info
+            };
             write_to_db(&config, ams_info, invoice_date.clone())?;
         }
     }
@@ -166,12 +212,13 @@ pub fn handle_command(config: Config, args: &AmsArgs) -> error::Result<()> {
 
 fn write_to_db(config: &Config, ams_info: AmsInfo, invoice_date: String) -> error::Result<()> {
     println!("Loading database file");
-    let mut tax_db: TaxDb = db::parse_db_with_default(config.db_location.as_str());
-    tax_db.add_ams_info(ams_info, invoice_date);
-    tax_db.write_to_file(config.db_location.as_str())?;
+    let storage = storage::open_storage(config);
+    let _lock = storage.lock()?;
+    let id = storage.insert_ams(ams_info, invoice_date)?;
     println!(
-        "Successfully updated DB file: {}",
+        "Successfully updated DB file: {} (entry id: {})",
         config.db_location.as_str(),
+        id,
     );
     Ok(())
 }
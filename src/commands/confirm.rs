@@ -0,0 +1,34 @@
+use std::io::{self, BufRead, Write};
+
+use crate::error;
+
+/// Asks user to confirm an action on stdin. Anything other than "y" or "yes" is a refusal
+pub fn confirm(message: &str, assume_yes: bool) -> error::Result<bool> {
+    if assume_yes {
+        return Ok(true);
+    }
+    print!("{} [y/N] ", message);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks user to pick one of the options on stdin, until one of them is picked.
+/// Returns index of the picked option.
+pub fn choose(message: &str, options: &[&str]) -> error::Result<usize> {
+    loop {
+        print!("{} [{}] ", message, options.join("/"));
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(error::Error::UserError(error::UserErrorKind::Generic(
+                "No answer given".to_string(),
+            )));
+        }
+        let answer = answer.trim().to_lowercase();
+        if let Some(index) = options.iter().position(|option| *option == answer) {
+            return Ok(index);
+        }
+    }
+}
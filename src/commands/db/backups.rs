@@ -0,0 +1,37 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::storage::{self, backup},
+    error,
+};
+
+#[derive(Parser, Debug)]
+pub struct BackupsArgs {}
+
+pub fn handle_command(config: Config, _args: &BackupsArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    let backups = backup::list_backups(storage.location())?;
+    if backups.is_empty() {
+        println!("No backups found for database: {}", storage.location());
+        return Ok(());
+    }
+
+    println!("{:<24} {:>12} {:>10}", "Backup", "AMS entries", "GPD years");
+    for backup in backups {
+        let backup_db = storage::storage_for(
+            backup.path.to_str().unwrap_or_default(),
+            Some(storage.backend()),
+        )
+        .load()?;
+        println!(
+            "{:<24} {:>12} {:>10}",
+            backup.name,
+            backup_db.ams.len(),
+            backup_db.gpd.len()
+        );
+    }
+    Ok(())
+}
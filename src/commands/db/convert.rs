@@ -0,0 +1,64 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::storage::{self, DbBackend},
+    error::{self, Error, UserErrorKind},
+};
+
+#[derive(Parser, Debug)]
+pub struct ConvertArgs {
+    #[clap(help = "Path of the converted database")]
+    target: String,
+    #[clap(
+        long,
+        help = "Backend of the converted database (json, sqlite). By default it is picked based on target extension"
+    )]
+    backend: Option<DbBackend>,
+    #[clap(long, help = "Overwrite target database if it already exists")]
+    force: bool,
+}
+
+pub fn handle_command(config: Config, args: &ConvertArgs) -> error::Result<()> {
+    let source = storage::open_storage(&config);
+    if !source.exists() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Database not found at: {}",
+            source.location()
+        ))));
+    }
+    // Converted database would be written in plain text
+    if source.encryption_salt()?.is_some() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Database is encrypted: {}. Only decrypted databases can be converted, use `fbihtax db decrypt` first",
+            source.location()
+        ))));
+    }
+    let target = storage::storage_for(args.target.as_str(), args.backend);
+    if target.exists() {
+        if !args.force {
+            return Err(Error::UserError(UserErrorKind::Generic(format!(
+                "Target database already exists: {}. Use --force to overwrite it",
+                target.location()
+            ))));
+        }
+        std::fs::remove_file(target.location())?;
+    }
+
+    let tax_db = source.load()?;
+    target.save(&tax_db)?;
+    let backend = args
+        .backend
+        .unwrap_or_else(|| DbBackend::from_location(args.target.as_str()));
+    println!(
+        "Converted {} entries and {} GPD results to {} database: {}",
+        tax_db.ams.len(),
+        tax_db.gpd.len(),
+        backend,
+        target.location()
+    );
+    println!("Set dbLocation (and dbBackend, if needed) in your config to start using it");
+    Ok(())
+}
@@ -0,0 +1,24 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::storage::{self, DbBackend},
+    error,
+};
+
+#[derive(Parser, Debug)]
+pub struct DecryptArgs {}
+
+pub fn handle_command(config: Config, _args: &DecryptArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    if storage.backend() != DbBackend::Json || storage.encryption_salt()?.is_none() {
+        println!("Database is not encrypted: {}", storage.location());
+        return Ok(());
+    }
+    let _lock = storage.lock()?;
+    storage::set_json_encryption(storage.location(), None)?;
+    println!("Decrypted DB file: {}", storage.location());
+    Ok(())
+}
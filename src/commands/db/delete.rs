@@ -0,0 +1,123 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    commands::confirm::confirm,
+    config::Config,
+    dates,
+    db::{
+        self,
+        storage::{self, DbStorage},
+        AmsFilter, AmsInfo,
+    },
+    error::{self, Error, UserErrorKind},
+    format::printer::{ListPrinter, TablePrinter, STDOUT_FILE},
+};
+
+#[derive(Parser, Debug)]
+pub struct DeleteArgs {
+    #[clap(
+        index(1),
+        help = "Id of the entry to delete, or date (YYYY-MM-DD) to delete all entries from that day"
+    )]
+    entry: String,
+    #[clap(short, long, help = "Delete without asking for confirmation")]
+    yes: bool,
+}
+
+/// Entries selected by id, or by date (YYYY-MM-DD) to select all entries from that day
+fn entries_to_delete(storage: &dyn DbStorage, entry: &str) -> error::Result<Vec<AmsInfo>> {
+    let entries: Vec<AmsInfo> = match entry.parse::<u32>() {
+        Ok(id) => storage.get_ams(id)?.into_iter().collect(),
+        Err(_) => {
+            let date = dates::parse_date(entry)?;
+            let filter = AmsFilter {
+                from: Some(date),
+                to: Some(date),
+                ..Default::default()
+            };
+            storage.find_ams(&filter)?
+        }
+    };
+    if entries.is_empty() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "No entries found for: {}",
+            entry
+        ))));
+    }
+    Ok(entries)
+}
+
+pub fn handle_command(config: Config, args: &DeleteArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let entries = entries_to_delete(storage.as_ref(), &args.entry)?;
+
+    let table_printer = TablePrinter {
+        columns: db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect(),
+    };
+    table_printer.write_list_to_file(
+        entries.iter().map(|entry| entry.to_dict()).collect(),
+        None,
+        STDOUT_FILE,
+    )?;
+    if !confirm(
+        format!("Delete {} entries listed above?", entries.len()).as_str(),
+        args.yes,
+    )? {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Aborted, database was not changed".to_string(),
+        )));
+    }
+
+    for entry in &entries {
+        storage.delete_ams(entry.id)?;
+    }
+    println!("Deleted {} entries", entries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn entries_to_delete_test() {
+        let location =
+            std::env::temp_dir().join(format!("fbihtax-delete-{}.json", std::process::id()));
+        let storage = storage::storage_for(location.to_str().unwrap(), None);
+        for (income_total, date) in [
+            (dec!(100), "2021-03-02"),
+            (dec!(200), "2021-03-02"),
+            (dec!(300), "2021-04-05"),
+        ] {
+            storage
+                .insert_ams(
+                    AmsInfo {
+                        income_total,
+                        ..Default::default()
+                    },
+                    date.to_string(),
+                )
+                .unwrap();
+        }
+
+        let ids = |entry: &str| -> Vec<u32> {
+            entries_to_delete(storage.as_ref(), entry)
+                .unwrap()
+                .iter()
+                .map(|v| v.id)
+                .collect()
+        };
+        assert_eq!(vec![3], ids("3"));
+        assert_eq!(vec![1, 2], ids("2021-03-02"));
+        assert!(entries_to_delete(storage.as_ref(), "4").is_err());
+        assert!(entries_to_delete(storage.as_ref(), "2021-05-01").is_err());
+        assert!(entries_to_delete(storage.as_ref(), "2021-13-01").is_err());
+
+        std::fs::remove_file(location).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+extern crate clap;
+
+use std::collections::HashMap;
+
+use chrono::Local;
+use clap::Parser;
+
+use crate::{
+    commands::db::list::TotalsMode,
+    config::Config,
+    dates,
+    db::{self, storage, AmsInfo, TaxDb},
+    error::{self, Error, UserErrorKind},
+    format::printer::{CsvPrinter, JsonPrinter, ListPrinter, TablePrinter, STDOUT_FILE},
+    format::OutputFormat,
+};
+
+static DUE_COLUMNS: [&str; 6] = ["id", "date", "client", "taxPaid", "dueDate", "status"];
+
+#[derive(Parser, Debug)]
+pub struct DueArgs {
+    #[clap(long, help = "Show only overdue entries")]
+    overdue: bool,
+    #[clap(
+        long,
+        help = "Date to check due dates against (YYYY-MM-DD). Defaults to today"
+    )]
+    as_of: Option<String>,
+    #[clap(
+        long,
+        help = "Exit with an error if any entry is overdue (useful for automated alerts)"
+    )]
+    fail_on_overdue: bool,
+    #[clap(long, help = "Show only entries of this taxpayer (profile id or JMBG)")]
+    taxpayer: Option<String>,
+    #[clap(long, help = "Output format (table, JSON, CSV)", default_value_t = OutputFormat::Table)]
+    output_format: OutputFormat,
+    #[clap(
+        short,
+        long,
+        help = "Path to save output file to (prints to stdout by default)",
+        default_value = STDOUT_FILE
+    )]
+    output: String,
+    #[clap(
+        long,
+        help = "When to end the listing with a totals row (auto, always, never). With auto, it is left out of CSV output only",
+        default_value_t = TotalsMode::Auto
+    )]
+    totals: TotalsMode,
+}
+
+pub fn handle_command(config: Config, args: &DueArgs) -> error::Result<()> {
+    let columns: Vec<String> = DUE_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let json_printer = JsonPrinter::default();
+    let table_printer = TablePrinter {
+        columns: columns.clone(),
+    };
+    let csv_printer = CsvPrinter { columns };
+
+    let printer: &dyn ListPrinter = match args.output_format {
+        OutputFormat::Table => &table_printer,
+        OutputFormat::Json => &json_printer,
+        OutputFormat::Csv => &csv_printer,
+        format => {
+            return Err(Error::UserError(UserErrorKind::UnsupportedOutputFormat(
+                format,
+            )))
+        }
+    };
+    let today = match &args.as_of {
+        Some(date) => dates::parse_date(date)?,
+        None => Local::today().naive_local(),
+    };
+
+    let taxpayer = config.taxpayer_id(args.taxpayer.as_ref())?;
+
+    let storage = storage::open_storage(&config);
+    let tax_db: TaxDb = storage.load()?;
+    let mut entries: Vec<&AmsInfo> = tax_db
+        .ams
+        .iter()
+        .filter(|entry| !entry.paid)
+        .filter(|entry| taxpayer.is_none() || entry.taxpayer == taxpayer)
+        .filter(|entry| !args.overdue || entry.is_overdue(today))
+        .collect();
+    entries.sort_by(|a, b| a.due_date.cmp(&b.due_date).then(a.id.cmp(&b.id)));
+    let overdue_count = entries
+        .iter()
+        .filter(|entry| entry.is_overdue(today))
+        .count();
+
+    let only_due_columns = |mut row: HashMap<String, String>| {
+        row.retain(|column, _| DUE_COLUMNS.contains(&column.as_str()));
+        row
+    };
+    let rows: Vec<HashMap<String, String>> = entries
+        .iter()
+        .map(|entry| {
+            let mut row = entry.to_dict();
+            let status = if entry.is_overdue(today) {
+                "overdue"
+            } else {
+                "due"
+            };
+            row.insert("status".to_string(), status.to_string());
+            only_due_columns(row)
+        })
+        .collect();
+    let totals = if args.totals.includes_totals(args.output_format) {
+        Some(only_due_columns(db::ams_totals_to_dict(&entries)))
+    } else {
+        None
+    };
+    printer.write_list_to_file(rows, totals, args.output.as_str())?;
+
+    if args.fail_on_overdue && overdue_count > 0 {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "{} entries are overdue",
+            overdue_count
+        ))));
+    }
+    Ok(())
+}
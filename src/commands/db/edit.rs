@@ -0,0 +1,88 @@
+extern crate clap;
+extern crate rust_decimal;
+
+use clap::Parser;
+use rust_decimal::Decimal;
+
+use crate::{
+    commands::confirm::confirm,
+    config::Config,
+    db::{self, storage, AmsChanges},
+    error::{self, Error, UserErrorKind},
+    format::printer::{ListPrinter, TablePrinter, STDOUT_FILE},
+};
+
+#[derive(Parser, Debug)]
+pub struct EditArgs {
+    #[clap(index(1), help = "Id of the entry to edit")]
+    id: u32,
+    #[clap(
+        short,
+        long,
+        help = "New decimal income value in BAM (will be rounded to 2 decimals)"
+    )]
+    income: Option<Decimal>,
+    #[clap(
+        short,
+        long,
+        help = "New decimal income value in BAM (will be rounded to 2 decimals) after deduction"
+    )]
+    deduced_income: Option<Decimal>,
+    #[clap(
+        long,
+        help = "New tax deduction percentage (20 default, 30 for income from authored work). Applied only when income is used and not deduced income"
+    )]
+    deduction_percentage: Option<Decimal>,
+    #[clap(long, help = "New invoice date (YYYY-MM-DD)")]
+    invoice_date: Option<String>,
+    #[clap(long, help = "New client name")]
+    client: Option<String>,
+    #[clap(long, help = "New currency the client paid in")]
+    currency: Option<String>,
+    #[clap(long, help = "New taxpayer (profile id or JMBG)")]
+    taxpayer: Option<String>,
+    #[clap(short, long, help = "Edit without asking for confirmation")]
+    yes: bool,
+}
+
+pub fn handle_command(config: Config, args: &EditArgs) -> error::Result<()> {
+    let changes = AmsChanges {
+        income: args.income,
+        deduced_income: args.deduced_income,
+        deduction_percentage: args.deduction_percentage,
+        invoice_date: args.invoice_date.clone(),
+        client: args.client.clone(),
+        currency: args.currency.clone(),
+        taxpayer: config.taxpayer_id(args.taxpayer.as_ref())?,
+    };
+
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let entry = storage
+        .get_ams(args.id)?
+        .ok_or(Error::UserError(UserErrorKind::Generic(format!(
+            "No entry found with id: {}",
+            args.id
+        ))))?;
+    let edited = entry.with_changes(&changes)?;
+    let (before, after) = (entry.to_dict(), edited.to_dict());
+
+    if before == after {
+        println!("Nothing to change");
+        return Ok(());
+    }
+
+    let table_printer = TablePrinter {
+        columns: db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect(),
+    };
+    table_printer.write_list_to_file(vec![before, after], None, STDOUT_FILE)?;
+    if !confirm("Replace the first entry with the second?", args.yes)? {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Aborted, database was not changed".to_string(),
+        )));
+    }
+
+    storage.update_ams(&edited)?;
+    println!("Updated entry with id: {}", args.id);
+    Ok(())
+}
@@ -0,0 +1,44 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{
+        crypto,
+        storage::{self, DbBackend},
+    },
+    error::{self, Error, UserErrorKind},
+};
+
+#[derive(Parser, Debug)]
+pub struct EncryptArgs {}
+
+pub fn handle_command(config: Config, _args: &EncryptArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    if storage.backend() != DbBackend::Json {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Encryption is supported only for JSON database".to_string(),
+        )));
+    }
+    if !storage.exists() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Database not found at: {}",
+            storage.location()
+        ))));
+    }
+    let _lock = storage.lock()?;
+    if storage.encryption_salt()?.is_some() {
+        println!("Database is already encrypted: {}", storage.location());
+        return Ok(());
+    }
+
+    crypto::passphrase(true)?;
+    storage::set_json_encryption(storage.location(), Some(crypto::new_salt().as_str()))?;
+    println!(
+        "Encrypted DB file: {}. Set {} to avoid being asked for the passphrase",
+        storage.location(),
+        crypto::PASSPHRASE_ENV
+    );
+    Ok(())
+}
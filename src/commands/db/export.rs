@@ -0,0 +1,58 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{self, storage, AmsFilter},
+    error::{self, Error, UserErrorKind},
+    format::printer::{CsvPrinter, JsonPrinter, ListPrinter, STDOUT_FILE},
+    format::OutputFormat,
+};
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    #[clap(long = "format", help = "Export format (CSV, JSON)", default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+    #[clap(
+        short,
+        long,
+        help = "Path to save exported file to (prints to stdout by default)",
+        default_value = STDOUT_FILE
+    )]
+    output: String,
+    #[clap(
+        long,
+        help = "Export only entries of this taxpayer (profile id or JMBG)"
+    )]
+    taxpayer: Option<String>,
+}
+
+pub fn handle_command(config: Config, args: &ExportArgs) -> error::Result<()> {
+    let json_printer = JsonPrinter::default();
+    let csv_printer = CsvPrinter {
+        columns: db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect(),
+    };
+
+    let printer: &dyn ListPrinter = match args.output_format {
+        OutputFormat::Csv => &csv_printer,
+        OutputFormat::Json => &json_printer,
+        format => {
+            return Err(Error::UserError(UserErrorKind::UnsupportedOutputFormat(
+                format,
+            )))
+        }
+    };
+
+    let filter = AmsFilter {
+        taxpayer: config.taxpayer_id(args.taxpayer.as_ref())?,
+        ..Default::default()
+    };
+    let storage = storage::open_storage(&config);
+    let entries = storage.find_ams(&filter)?;
+    printer.write_list_to_file(
+        entries.iter().map(|entry| entry.to_export_dict()).collect(),
+        None,
+        args.output.as_str(),
+    )
+}
@@ -0,0 +1,193 @@
+extern crate clap;
+
+use std::collections::HashMap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{self, storage, AmsInfo, TaxDb},
+    error::{self, Error, UserErrorKind},
+};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DuplicateStrategy {
+    Skip,
+    Update,
+}
+
+impl std::fmt::Display for DuplicateStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            DuplicateStrategy::Skip => "skip",
+            DuplicateStrategy::Update => "update",
+        })
+    }
+}
+
+impl std::str::FromStr for DuplicateStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(DuplicateStrategy::Skip),
+            "update" => Ok(DuplicateStrategy::Update),
+            _ => Err("Unknown duplicate strategy passed!".to_string()),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportArgs {
+    #[clap(index(1), help = "CSV file to import")]
+    file: String,
+    #[clap(
+        long = "map",
+        help = "Read entry field from a differently named CSV column (field=column), e.g. --map incomeTotal=Net"
+    )]
+    mappings: Vec<String>,
+    #[clap(long, help = "CSV column delimiter", default_value_t = ',')]
+    delimiter: char,
+    #[clap(
+        long,
+        help = "What to do with entries already present in the database (skip, update)",
+        default_value_t = DuplicateStrategy::Skip
+    )]
+    on_duplicate: DuplicateStrategy,
+    #[clap(
+        long,
+        help = "Taxpayer of imported entries which don't specify one (profile id or JMBG)"
+    )]
+    taxpayer: Option<String>,
+}
+
+fn parse_mappings(mappings: &[String]) -> error::Result<HashMap<String, String>> {
+    let mut result = HashMap::new();
+    for mapping in mappings {
+        let (field, column) =
+            mapping
+                .split_once('=')
+                .ok_or(Error::UserError(UserErrorKind::Generic(format!(
+                    "Invalid mapping: {}. Expected format is field=column",
+                    mapping
+                ))))?;
+        if !db::AMS_COLUMNS.contains(&field) {
+            return Err(Error::UserError(UserErrorKind::Generic(format!(
+                "Unknown field in mapping: {}. Known fields are: {}",
+                field,
+                db::AMS_COLUMNS.join(", ")
+            ))));
+        }
+        result.insert(field.to_string(), column.to_string());
+    }
+    Ok(result)
+}
+
+pub fn handle_command(config: Config, args: &ImportArgs) -> error::Result<()> {
+    let mappings = parse_mappings(&args.mappings)?;
+    let column_for = |field: &str| -> String {
+        mappings
+            .get(field)
+            .cloned()
+            .unwrap_or_else(|| field.to_string())
+    };
+
+    if !args.delimiter.is_ascii() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Invalid delimiter: {}. Only ASCII characters can be used as CSV delimiter",
+            args.delimiter
+        ))));
+    }
+    let taxpayer = config.taxpayer_id(args.taxpayer.as_ref())?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(args.delimiter as u8)
+        .trim(csv::Trim::All)
+        .from_path(&args.file)?;
+    let headers = reader.headers()?.clone();
+    let has_column = |field: &str| headers.iter().any(|h| h == column_for(field));
+    if !has_column("date") || !(has_column("incomeTotal") || has_column("income")) {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "CSV file must have {} column and either {} or {} column. Use --map to map differently named columns",
+            column_for("date"),
+            column_for("incomeTotal"),
+            column_for("income")
+        ))));
+    }
+
+    // Messages about single rows are printed to stderr, only the summary goes to stdout
+    let mut entries: Vec<(u64, AmsInfo)> = Vec::new();
+    let mut invalid_rows: Vec<String> = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let dict: HashMap<String, String> = db::AMS_COLUMNS
+            .iter()
+            .filter_map(|field| {
+                headers
+                    .iter()
+                    .position(|h| h == column_for(field))
+                    .and_then(|index| record.get(index))
+                    .map(|value| (field.to_string(), value.to_string()))
+            })
+            .collect();
+        match AmsInfo::from_dict(&dict) {
+            Ok(entry) => entries.push((
+                line,
+                AmsInfo {
+                    taxpayer: entry.taxpayer.clone().or_else(|| taxpayer.clone()),
+                    ..entry
+                },
+            )),
+            Err(err) => invalid_rows.push(format!("  line {}: {}", line, err)),
+        }
+    }
+    if !invalid_rows.is_empty() {
+        eprintln!("Invalid rows:\n{}", invalid_rows.join("\n"));
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Found {} invalid rows, nothing was imported",
+            invalid_rows.len()
+        ))));
+    }
+
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let mut tax_db: TaxDb = storage.load()?;
+    let existing_ids: Vec<u32> = tax_db.ams.iter().map(|v| v.id).collect();
+    let (mut imported, mut updated, mut skipped) = (0, 0, 0);
+    for (line, entry) in entries {
+        let existing = || tax_db.ams.iter().filter(|v| existing_ids.contains(&v.id));
+        let duplicate_id = existing().find(|v| entry.is_duplicate_of(v)).map(|v| v.id);
+        let similar_id = existing().find(|v| entry.is_similar_to(v)).map(|v| v.id);
+        match (duplicate_id, args.on_duplicate) {
+            (Some(id), DuplicateStrategy::Skip) => {
+                eprintln!("Line {}: duplicate of entry {}, skipped", line, id);
+                skipped += 1;
+            }
+            (Some(id), DuplicateStrategy::Update) => {
+                if let Some(existing) = tax_db.get_ams_mut(id) {
+                    *existing = AmsInfo { id, ..entry };
+                }
+                eprintln!("Line {}: duplicate of entry {}, updated", line, id);
+                updated += 1;
+            }
+            (None, _) => {
+                if let Some(id) = similar_id {
+                    eprintln!(
+                        "Line {}: same date and income as entry {}, imported as a separate payment",
+                        line, id
+                    );
+                }
+                let date = entry.date.clone();
+                tax_db.add_ams_info(entry, date);
+                imported += 1;
+            }
+        }
+    }
+
+    storage.save(&tax_db)?;
+    println!(
+        "Imported {} new entries, updated {}, skipped {}",
+        imported, updated, skipped
+    );
+    Ok(())
+}
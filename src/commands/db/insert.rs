@@ -7,7 +7,8 @@ use rust_decimal_macros::dec;
 
 use crate::{
     config::Config,
-    db::{self, TaxDb},
+    dates,
+    db::{self, storage},
     error::{self, Error, UserErrorKind},
     taxcalculator,
 };
@@ -34,6 +35,16 @@ pub struct InsertArgs {
     deduction_percentage: Decimal,
     #[clap(long, help = "Invoice date (YYYY-MM-DD)")]
     invoice_date: String,
+    #[clap(long, help = "Record the tax as not paid yet")]
+    unpaid: bool,
+    #[clap(long, help = "Date the tax was paid on (YYYY-MM-DD)")]
+    paid_date: Option<String>,
+    #[clap(long, help = "Reference of the tax payment")]
+    payment_reference: Option<String>,
+    #[clap(long, help = "Taxpayer the entry belongs to (profile id or JMBG)")]
+    taxpayer: Option<String>,
+    #[clap(long, help = "Currency the client paid in", default_value = "BAM")]
+    currency: String,
 }
 
 pub fn handle_command(config: Config, args: &InsertArgs) -> error::Result<()> {
@@ -43,7 +54,7 @@ pub fn handle_command(config: Config, args: &InsertArgs) -> error::Result<()> {
             args.deduction_percentage.round_dp(2),
         ),
         None => match &args.deduced_income {
-            Some(deduced_income) => deduced_income.clone(),
+            Some(deduced_income) => *deduced_income,
             None => {
                 return Err(Error::UserError(UserErrorKind::Generic(
                     "Provide either --income or --deduced-income!".to_string(),
@@ -51,13 +62,29 @@ pub fn handle_command(config: Config, args: &InsertArgs) -> error::Result<()> {
             }
         },
     };
-    let mut tax_db: TaxDb = db::parse_db_with_default(config.db_location.as_str());
-    tax_db.add_ams_info(
+    dates::parse_date(&args.invoice_date)?;
+    if let Some(paid_date) = &args.paid_date {
+        dates::parse_date(paid_date)?;
+    }
+    let taxpayer = config.taxpayer_id(args.taxpayer.as_ref())?;
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let id = storage.insert_ams(
         db::AmsInfo {
+            income: args.income.map(|inc| inc.round_dp(2)),
+            deduction_percentage: args.income.map(|_| args.deduction_percentage.round_dp(2)),
             income_total: income,
+            health_insurance: Some(taxcalculator::health_insurance_part(income)),
             tax_paid: taxcalculator::tax_amount(income),
+            paid: !args.unpaid,
+            paid_date: args.paid_date.clone(),
+            payment_reference: args.payment_reference.clone(),
+            taxpayer,
+            currency: args.currency.clone(),
+            ..Default::default()
         },
         args.invoice_date.clone(),
-    );
-    tax_db.write_to_file(config.db_location.as_str())
+    )?;
+    println!("Inserted entry with id: {}", id);
+    Ok(())
 }
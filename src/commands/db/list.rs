@@ -0,0 +1,137 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    dates,
+    db::{self, storage, AmsFilter, AmsInfo},
+    error::{self, Error, UserErrorKind},
+    format::printer::{CsvPrinter, JsonPrinter, ListPrinter, TablePrinter, STDOUT_FILE},
+    format::OutputFormat,
+};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TotalsMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::fmt::Display for TotalsMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            TotalsMode::Auto => "auto",
+            TotalsMode::Always => "always",
+            TotalsMode::Never => "never",
+        })
+    }
+}
+
+impl std::str::FromStr for TotalsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TotalsMode::Auto),
+            "always" => Ok(TotalsMode::Always),
+            "never" => Ok(TotalsMode::Never),
+            _ => Err("Unknown totals mode passed!".to_string()),
+        }
+    }
+}
+
+impl TotalsMode {
+    /// Whether the totals row is included in the given output format.
+    /// CSV output holds only entries by default, so it can be processed or imported as it is
+    pub fn includes_totals(&self, output_format: OutputFormat) -> bool {
+        match self {
+            TotalsMode::Auto => output_format != OutputFormat::Csv,
+            TotalsMode::Always => true,
+            TotalsMode::Never => false,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ListArgs {
+    #[clap(long, help = "Show only entries from this year (YYYY)")]
+    year: Option<i32>,
+    #[clap(long, help = "Show only entries from this quarter (1-4)")]
+    quarter: Option<u32>,
+    #[clap(long, help = "Show only entries from this month (1-12)")]
+    month: Option<u32>,
+    #[clap(long, help = "Show only entries for clients containing this name")]
+    client: Option<String>,
+    #[clap(long, help = "Show only entries of this taxpayer (profile id or JMBG)")]
+    taxpayer: Option<String>,
+    #[clap(long, help = "Show only entries from this date onwards (YYYY-MM-DD)")]
+    from: Option<String>,
+    #[clap(long, help = "Show only entries up to this date (YYYY-MM-DD)")]
+    to: Option<String>,
+    #[clap(long, help = "Output format (table, JSON, CSV)", default_value_t = OutputFormat::Table)]
+    output_format: OutputFormat,
+    #[clap(
+        short,
+        long,
+        help = "Path to save output file to (prints to stdout by default)",
+        default_value = STDOUT_FILE
+    )]
+    output: String,
+    #[clap(
+        long,
+        help = "When to end the listing with a totals row (auto, always, never). With auto, it is left out of CSV output only",
+        default_value_t = TotalsMode::Auto
+    )]
+    totals: TotalsMode,
+}
+
+pub fn handle_command(config: Config, args: &ListArgs) -> error::Result<()> {
+    let columns: Vec<String> = db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let json_printer = JsonPrinter::default();
+    let table_printer = TablePrinter {
+        columns: columns.clone(),
+    };
+    let csv_printer = CsvPrinter { columns };
+
+    let printer: &dyn ListPrinter = match args.output_format {
+        OutputFormat::Table => &table_printer,
+        OutputFormat::Json => &json_printer,
+        OutputFormat::Csv => &csv_printer,
+        format => {
+            return Err(Error::UserError(UserErrorKind::UnsupportedOutputFormat(
+                format,
+            )))
+        }
+    };
+
+    let filter = AmsFilter {
+        year: args.year,
+        quarter: args.quarter,
+        month: args.month,
+        client: args.client.clone(),
+        taxpayer: config.taxpayer_id(args.taxpayer.as_ref())?,
+        from: args.from.as_deref().map(dates::parse_date).transpose()?,
+        to: args.to.as_deref().map(dates::parse_date).transpose()?,
+    };
+
+    let storage = storage::open_storage(&config);
+    let entries = storage.find_ams(&filter)?;
+    if filter.filters_dates() {
+        // Printed to stderr to keep the listing itself parseable
+        if let Some(warning) = db::malformed_dates_warning(&storage.malformed_dates()?) {
+            eprintln!("{}", warning);
+        }
+    }
+    let entries: Vec<&AmsInfo> = entries.iter().collect();
+    let totals = if args.totals.includes_totals(args.output_format) {
+        Some(db::ams_totals_to_dict(&entries))
+    } else {
+        None
+    };
+    printer.write_list_to_file(
+        entries.iter().map(|entry| entry.to_dict()).collect(),
+        totals,
+        args.output.as_str(),
+    )
+}
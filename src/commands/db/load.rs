@@ -1,47 +1,528 @@
 extern crate clap;
 
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
 use clap::Parser;
+use sha2::{Digest, Sha256};
 
 use crate::{
     config::Config,
-    db::{self, TaxDb},
-    error,
-    forms::amsform::{self, FormField},
+    db::{self, storage, AmsInfo, TaxDb},
+    error::{self, Error, UserErrorKind},
+    fdf::fdf_generator,
+    forms::amsform::{self, AmsForm, FormField},
 };
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ConflictStrategy {
+    Skip,
+    Replace,
+    Fail,
+}
+
+impl std::fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            ConflictStrategy::Skip => "skip",
+            ConflictStrategy::Replace => "replace",
+            ConflictStrategy::Fail => "fail",
+        })
+    }
+}
+
+impl std::str::FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictStrategy::Skip),
+            "replace" => Ok(ConflictStrategy::Replace),
+            "fail" => Ok(ConflictStrategy::Fail),
+            _ => Err("Unknown conflict strategy passed!".to_string()),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 pub struct LoadArgs {
-    #[clap(index(1), multiple_values(true))]
+    #[clap(
+        index(1),
+        multiple_values(true),
+        help = "AMS forms to load (PDF, FDF, XFDF or JSON) or directories to search for them"
+    )]
     file: Vec<String>,
+    #[clap(
+        long,
+        help = "What to do with forms already present in the database (skip, replace, fail)",
+        default_value_t = ConflictStrategy::Skip
+    )]
+    on_conflict: ConflictStrategy,
+    #[clap(long, help = "Taxpayer loaded entries belong to (profile id or JMBG)")]
+    taxpayer: Option<String>,
+}
+
+/// Extensions of files picked up when searching directories
+static SOURCE_EXTENSIONS: [&str; 4] = ["pdf", "fdf", "xfdf", "json"];
+
+#[derive(Clone, Copy)]
+enum SourceFormat {
+    Pdf,
+    Fdf,
+    Xfdf,
+    Json,
+}
+
+fn unsupported_file<S: Into<String>>(reason: S) -> Error {
+    Error::UserError(UserErrorKind::Generic(reason.into()))
+}
+
+/// Detects format from the beginning of the file, regardless of its extension
+fn detect_format(file: &Path) -> error::Result<SourceFormat> {
+    let mut start = Vec::new();
+    fs::File::open(file)?.take(256).read_to_end(&mut start)?;
+    let start = String::from_utf8_lossy(&start);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    if start.starts_with("%PDF") {
+        Ok(SourceFormat::Pdf)
+    } else if start.starts_with("%FDF") {
+        Ok(SourceFormat::Fdf)
+    } else if start.starts_with("<?xml") || start.starts_with("<xfdf") {
+        Ok(SourceFormat::Xfdf)
+    } else if start.starts_with('{') {
+        Ok(SourceFormat::Json)
+    } else {
+        Err(unsupported_file("Unrecognized file format"))
+    }
+}
+
+/// Expands directories into files they contain, recursively
+fn collect_files(paths: &[String]) -> error::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            collect_dir(&path, &mut files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn collect_dir(dir: &Path, files: &mut Vec<PathBuf>) -> error::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_dir(&entry, files)?;
+        } else if entry
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| SOURCE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+            .unwrap_or(false)
+        {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Reads field values of FDF, XFDF and JSON outputs, keyed by PDF field names
+fn read_field_values(file: &Path, format: SourceFormat) -> error::Result<HashMap<String, String>> {
+    let content = fs::read_to_string(file)?;
+    match format {
+        SourceFormat::Fdf => Ok(fdf_generator::parse_fdf(content.as_str())),
+        SourceFormat::Xfdf => Ok(fdf_generator::parse_xfdf(content.as_str())),
+        SourceFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(content.as_str())?;
+            let object = value
+                .as_object()
+                .ok_or_else(|| unsupported_file("JSON file is not an AMS form output"))?;
+            Ok(object
+                .iter()
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                .collect())
+        }
+        SourceFormat::Pdf => Err(Error::UnexpectedCondition(
+            "PDF forms are loaded directly".to_string(),
+        )),
+    }
+}
+
+/// Loads AMS form from the file. Outputs other than PDF are filled into the cached blank form,
+/// since they only contain values keyed by its field names.
+fn read_form(config: &Config, file: &Path, format: SourceFormat) -> error::Result<AmsForm> {
+    let file_name = file.to_string_lossy().to_string();
+    if let SourceFormat::Pdf = format {
+        return amsform::load_ams_form(file_name);
+    }
+    let values = read_field_values(file, format)?;
+    if !Path::new(config.ams.cache_location.as_str()).exists() {
+        return Err(unsupported_file(format!(
+            "Cached AMS form not found at {}. It is needed to read FDF, XFDF and JSON outputs, run `fbihtax ams` once to download it",
+            config.ams.cache_location
+        )));
+    }
+    let mut form = amsform::load_ams_form(config.ams.cache_location.clone())?;
+    if form.fill_from_dict(&values)? == 0 {
+        return Err(unsupported_file("File does not contain AMS form fields"));
+    }
+    Ok(form)
+}
+
+/// SHA-256 of file contents
+fn fingerprint(file: &Path) -> error::Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(file)?)))
+}
+
+fn read_ams_info(form: &AmsForm, file: &Path) -> error::Result<AmsInfo> {
+    let total_paid = form.get_number_field_value(FormField::TaxToPayTotal)?;
+    let health_insurance = form.get_number_field_value(FormField::HealthInsuranceTotal)?;
+    let income = form.get_number_field_value(FormField::TaxBaseTotal)? + health_insurance;
+    let client = form.get_text_field_value(FormField::CompanyName)?;
+    let date = form.get_payment_date()?;
+
+    Ok(AmsInfo {
+        due_date: db::calculate_due_date(&date),
+        date,
+        income_total: income,
+        health_insurance: Some(health_insurance),
+        tax_paid: total_paid,
+        client: Some(client).filter(|name| !name.is_empty()),
+        form_path: Some(file.to_string_lossy().to_string()),
+        source_fingerprint: Some(fingerprint(file)?),
+        ..Default::default()
+    })
+}
+
+fn load_file(config: &Config, file: &Path) -> error::Result<AmsInfo> {
+    let format = detect_format(file)?;
+    let form = read_form(config, file, format)?;
+    read_ams_info(&form, file)
+}
+
+/// Change of the database made by loading a single file
+#[derive(Debug)]
+enum LoadChange {
+    /// Entry was imported under this id
+    Import(u32),
+    /// Entry with this id was replaced, keeping its payment status
+    Replace(u32),
+    /// Entry was already loaded from the same file as the entry with this id
+    Skip(u32),
+    /// Entry with this id was loaded from the same file before fingerprints were stored.
+    /// It is skipped like other already loaded files, but its fingerprint is stored now.
+    Fingerprint(u32),
+}
+
+/// Whether the entry was loaded from the same file as the existing one. Entries loaded
+/// before fingerprints were stored are matched by their form path and date instead, since
+/// a file under the same path with a different date is a newer form which replaced it.
+fn loaded_from_same_file(entry: &AmsInfo, existing: &AmsInfo) -> bool {
+    entry.is_duplicate_of(existing)
+        || (existing.source_fingerprint.is_none()
+            && existing.form_path.is_some()
+            && existing.form_path == entry.form_path
+            && existing.date == entry.date)
+}
+
+/// Existing entry with the same date and income, which might record the same payment
+fn find_similar(tax_db: &TaxDb, entry: &AmsInfo) -> Option<u32> {
+    tax_db
+        .ams
+        .iter()
+        .find(|existing| entry.is_similar_to(existing))
+        .map(|existing| existing.id)
+}
+
+/// Decides how the entry loaded from the file changes the database. Only entries loaded
+/// from the same file conflict with it. Loaded database is updated as well, so files loaded
+/// later are checked against the ones loaded before them.
+fn load_entry(
+    tax_db: &mut TaxDb,
+    entry: AmsInfo,
+    on_conflict: ConflictStrategy,
+    file: &Path,
+) -> error::Result<LoadChange> {
+    let existing = match tax_db
+        .ams
+        .iter_mut()
+        .find(|existing| loaded_from_same_file(&entry, existing))
+    {
+        Some(existing) => existing,
+        None => {
+            let date = entry.date.clone();
+            return Ok(LoadChange::Import(tax_db.add_ams_info(entry, date)));
+        }
+    };
+    match on_conflict {
+        ConflictStrategy::Skip if existing.source_fingerprint.is_none() => {
+            existing.source_fingerprint = entry.source_fingerprint;
+            Ok(LoadChange::Fingerprint(existing.id))
+        }
+        ConflictStrategy::Skip => Ok(LoadChange::Skip(existing.id)),
+        ConflictStrategy::Replace => {
+            // Forms don't contain payment status, so it is kept from the replaced entry
+            *existing = AmsInfo {
+                id: existing.id,
+                paid: existing.paid,
+                paid_date: existing.paid_date.clone(),
+                payment_reference: existing.payment_reference.clone(),
+                ..entry
+            };
+            Ok(LoadChange::Replace(existing.id))
+        }
+        ConflictStrategy::Fail => Err(Error::UserError(UserErrorKind::Generic(format!(
+            "{} is already loaded as entry {}, nothing was loaded",
+            file.display(),
+            existing.id
+        )))),
+    }
 }
 
 pub fn handle_command(config: Config, args: &LoadArgs) -> error::Result<()> {
-    let mut tax_db: TaxDb = db::parse_db_with_default(config.db_location.as_str());
+    let taxpayer = config.taxpayer_id(args.taxpayer.as_ref())?;
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let mut tax_db: TaxDb = storage.load()?;
+
+    let files = collect_files(&args.file)?;
+    let (mut skipped, mut unreadable) = (0, 0);
+    // Files are loaded into the database in memory, which is saved only once all of them
+    // are checked, so a conflict stops the whole load
+    let mut changes: Vec<(&PathBuf, LoadChange)> = Vec::new();
+    for file in &files {
+        let entry = match load_file(&config, file) {
+            Ok(entry) => AmsInfo {
+                taxpayer: taxpayer.clone(),
+                ..entry
+            },
+            Err(err) => {
+                eprintln!("Can't read {}: {}", file.display(), err);
+                unreadable += 1;
+                continue;
+            }
+        };
+        let similar = find_similar(&tax_db, &entry);
+        match load_entry(&mut tax_db, entry, args.on_conflict, file)? {
+            LoadChange::Skip(id) => {
+                println!("Skipped {}: already loaded as entry {}", file.display(), id);
+                skipped += 1;
+            }
+            LoadChange::Fingerprint(id) => {
+                println!("Skipped {}: already loaded as entry {}", file.display(), id);
+                skipped += 1;
+                changes.push((file, LoadChange::Fingerprint(id)));
+            }
+            change => {
+                if let (LoadChange::Import(_), Some(similar)) = (&change, similar) {
+                    eprintln!(
+                        "Warning: {} has the same date and income as entry {}, it is loaded as a separate payment",
+                        file.display(),
+                        similar
+                    );
+                }
+                changes.push((file, change));
+            }
+        }
+    }
+    if !changes.is_empty() {
+        storage.save(&tax_db)?;
+    }
+
+    let (mut imported, mut replaced) = (0, 0);
+    for (file, change) in changes {
+        match change {
+            LoadChange::Import(id) => {
+                println!("Imported {} as entry with id: {}", file.display(), id);
+                imported += 1;
+            }
+            LoadChange::Replace(id) => {
+                println!("Replaced entry {} with {}", id, file.display());
+                replaced += 1;
+            }
+            LoadChange::Skip(_) | LoadChange::Fingerprint(_) => {}
+        }
+    }
+    println!(
+        "Imported {} new entries, replaced {} and skipped {} already loaded",
+        imported, replaced, skipped
+    );
+    if unreadable > 0 {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "{} files could not be read, other files were loaded",
+            unreadable
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
 
-    for file in &args.file {
-        let form = amsform::load_ams_form(file.clone())?;
+    use super::*;
 
-        let total_paid = form.get_number_field_value(FormField::TaxToPayTotal)?;
-        let income = form.get_number_field_value(FormField::TaxBaseTotal)?
-            + form.get_number_field_value(FormField::HealthInsuranceTotal)?;
+    fn loaded_entry(fingerprint: &str) -> AmsInfo {
+        AmsInfo {
+            date: "2021-03-10".to_string(),
+            income_total: dec!(800),
+            tax_paid: dec!(76.8),
+            paid: false,
+            source_fingerprint: Some(fingerprint.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn paid_db() -> TaxDb {
+        let mut tax_db = TaxDb::default();
+        tax_db.add_ams_info(
+            AmsInfo {
+                paid: true,
+                paid_date: Some("2021-04-05".to_string()),
+                payment_reference: Some("1234567890".to_string()),
+                ..loaded_entry("first")
+            },
+            "2021-03-10".to_string(),
+        );
+        tax_db
+    }
+
+    #[test]
+    fn load_entry_skip_test() {
+        let file = Path::new("amsform.pdf");
+        let mut tax_db = paid_db();
+
+        let change = load_entry(
+            &mut tax_db,
+            loaded_entry("first"),
+            ConflictStrategy::Skip,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Skip(1)));
+        assert_eq!(1, tax_db.ams.len());
 
-        let invoice_date = [
-            form.get_text_field_value(FormField::PaymentDateYear)?,
-            "-".to_string(),
-            form.get_text_field_value(FormField::PaymentDateMonth)?,
-            "-".to_string(),
-            form.get_text_field_value(FormField::PaymentDateDay)?,
-        ]
-        .concat();
+        // Same date and income from another file is a separate payment
+        let entry = loaded_entry("second");
+        assert_eq!(Some(1), find_similar(&tax_db, &entry));
+        let change = load_entry(&mut tax_db, entry, ConflictStrategy::Skip, file).unwrap();
+        assert!(matches!(change, LoadChange::Import(_)));
+        assert_eq!(2, tax_db.ams.len());
+
+        // Files loaded in the same run conflict with each other
+        let change = load_entry(
+            &mut tax_db,
+            loaded_entry("second"),
+            ConflictStrategy::Skip,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Skip(2)));
+    }
 
+    #[test]
+    fn load_entry_without_fingerprint_test() {
+        let file = Path::new("output/amsform.pdf");
+        let from_file = |fingerprint: &str, date: &str| AmsInfo {
+            date: date.to_string(),
+            form_path: Some(file.to_string_lossy().to_string()),
+            ..loaded_entry(fingerprint)
+        };
+        let mut tax_db = TaxDb::default();
+        // Loaded before fingerprints were stored
         tax_db.add_ams_info(
-            db::AmsInfo {
-                income_total: income,
-                tax_paid: total_paid,
+            AmsInfo {
+                source_fingerprint: None,
+                ..from_file("", "2021-03-10")
             },
-            invoice_date,
+            "2021-03-10".to_string(),
         );
+
+        let change = load_entry(
+            &mut tax_db,
+            from_file("first", "2021-03-10"),
+            ConflictStrategy::Skip,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Fingerprint(1)));
+        assert_eq!(
+            Some("first".to_string()),
+            tax_db.get_ams(1).unwrap().source_fingerprint
+        );
+        let change = load_entry(
+            &mut tax_db,
+            from_file("first", "2021-03-10"),
+            ConflictStrategy::Skip,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Skip(1)));
+
+        // Newer form saved under the same path
+        let change = load_entry(
+            &mut tax_db,
+            from_file("second", "2021-04-10"),
+            ConflictStrategy::Skip,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Import(2)));
+        assert_eq!(2, tax_db.ams.len());
     }
 
-    tax_db.write_to_file(config.db_location.as_str())
+    #[test]
+    fn load_entry_replace_test() {
+        let file = Path::new("amsform.pdf");
+        let mut tax_db = paid_db();
+
+        let change = load_entry(
+            &mut tax_db,
+            AmsInfo {
+                client: Some("Acme".to_string()),
+                ..loaded_entry("first")
+            },
+            ConflictStrategy::Replace,
+            file,
+        )
+        .unwrap();
+        assert!(matches!(change, LoadChange::Replace(1)));
+        let replaced = tax_db.get_ams(1).unwrap();
+        assert_eq!(Some("Acme".to_string()), replaced.client);
+        // Payment status is kept from the replaced entry
+        assert!(replaced.paid);
+        assert_eq!(Some("2021-04-05".to_string()), replaced.paid_date);
+        assert_eq!(Some("1234567890".to_string()), replaced.payment_reference);
+        assert_eq!(1, tax_db.ams.len());
+    }
+
+    #[test]
+    fn load_entry_fail_test() {
+        let file = Path::new("amsform.pdf");
+        let mut tax_db = paid_db();
+
+        assert!(load_entry(
+            &mut tax_db,
+            loaded_entry("first"),
+            ConflictStrategy::Fail,
+            file
+        )
+        .is_err());
+        assert!(load_entry(
+            &mut tax_db,
+            loaded_entry("second"),
+            ConflictStrategy::Fail,
+            file
+        )
+        .is_ok());
+        assert_eq!(2, tax_db.ams.len());
+    }
 }
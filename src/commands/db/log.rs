@@ -0,0 +1,79 @@
+extern crate clap;
+
+use clap::Parser;
+use serde_json::Value;
+
+use crate::{
+    config::Config,
+    db::storage::{
+        self,
+        journal::{self, ChangeKind, RecordKind},
+    },
+    error,
+};
+
+#[derive(Parser, Debug)]
+pub struct LogArgs {
+    #[clap(long, help = "Show history of the income tax entry with this id")]
+    id: Option<u32>,
+    #[clap(
+        long,
+        help = "Show history of income tax entries and GPD results for this year (YYYY)"
+    )]
+    year: Option<String>,
+}
+
+fn format_value(value: &Option<Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Null) | None => "-".to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+pub fn handle_command(config: Config, args: &LogArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    let entries: Vec<journal::JournalEntry> = journal::read_journal(storage.location())?
+        .into_iter()
+        .filter(|entry| {
+            args.id
+                .map(|id| entry.record == RecordKind::Ams && entry.key == id.to_string())
+                .unwrap_or(true)
+        })
+        .filter(|entry| {
+            args.year
+                .as_ref()
+                .map(|year| entry.year().as_ref() == Some(year))
+                .unwrap_or(true)
+        })
+        .collect();
+    if entries.is_empty() {
+        println!("No changes recorded");
+        return Ok(());
+    }
+
+    for entry in entries {
+        let record = match entry.record {
+            RecordKind::Ams => format!("AMS entry {}", entry.key),
+            RecordKind::Gpd => format!("GPD result for {}", entry.key),
+        };
+        let change = match entry.change {
+            ChangeKind::Added => "added",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Removed => "removed",
+        };
+        println!(
+            "{}  {} {} by `fbihtax {}`",
+            entry.timestamp, record, change, entry.command
+        );
+        for (field, before, after) in entry.changed_fields() {
+            println!(
+                "    {}: {} -> {}",
+                field,
+                format_value(&before),
+                format_value(&after)
+            );
+        }
+    }
+    Ok(())
+}
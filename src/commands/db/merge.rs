@@ -0,0 +1,156 @@
+extern crate clap;
+
+use clap::Parser;
+use serde_json::Value;
+
+use crate::{
+    commands::confirm::choose,
+    config::Config,
+    db::{
+        self,
+        merge::{self, Conflict, Side},
+        storage::{self, DbStorage},
+    },
+    error::{self, Error, UserErrorKind},
+    format::printer::{ListPrinter, TablePrinter, STDOUT_FILE},
+};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ConflictStrategy {
+    Ask,
+    First,
+    Second,
+    Fail,
+}
+
+impl std::fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            ConflictStrategy::Ask => "ask",
+            ConflictStrategy::First => "first",
+            ConflictStrategy::Second => "second",
+            ConflictStrategy::Fail => "fail",
+        })
+    }
+}
+
+impl std::str::FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(ConflictStrategy::Ask),
+            "first" => Ok(ConflictStrategy::First),
+            "second" => Ok(ConflictStrategy::Second),
+            "fail" => Ok(ConflictStrategy::Fail),
+            _ => Err("Unknown conflict strategy passed!".to_string()),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct MergeArgs {
+    #[clap(index(1), help = "First copy of the database")]
+    first: String,
+    #[clap(index(2), help = "Second copy of the database")]
+    second: String,
+    #[clap(
+        short,
+        long,
+        help = "Path to save the merged database to. By default, the configured database is replaced"
+    )]
+    output: Option<String>,
+    #[clap(
+        long,
+        help = "How to resolve records which differ between copies (ask, first, second, fail)",
+        default_value_t = ConflictStrategy::Ask
+    )]
+    on_conflict: ConflictStrategy,
+}
+
+fn conflict_name(conflict: &Conflict) -> String {
+    match conflict {
+        Conflict::Ams(first, _) => format!("AMS {} ({})", first.id, first.date),
+        Conflict::AmsRemoved(side, entry) => format!(
+            "AMS {} ({}), missing from the {} copy",
+            entry.id,
+            entry.date,
+            match side {
+                Side::First => "second",
+                Side::Second => "first",
+            }
+        ),
+        Conflict::Gpd(key, _, _) => format!("GPD {}", key),
+    }
+}
+
+fn print_conflict(conflict: &Conflict) -> error::Result<()> {
+    println!("Conflicting records for {}:", conflict_name(conflict));
+    match conflict {
+        Conflict::Ams(first, second) => TablePrinter {
+            columns: db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect(),
+        }
+        .write_list_to_file(vec![first.to_dict(), second.to_dict()], None, STDOUT_FILE),
+        Conflict::AmsRemoved(_, entry) => {
+            println!("It was most likely removed from the other copy, pick that copy to remove it");
+            TablePrinter {
+                columns: db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect(),
+            }
+            .write_list_to_file(vec![entry.to_dict()], None, STDOUT_FILE)
+        }
+        Conflict::Gpd(_, first, second) => {
+            let (first, second) = (serde_json::to_value(first)?, serde_json::to_value(second)?);
+            if let (Value::Object(first), Value::Object(second)) = (&first, &second) {
+                for (field, first_value) in first {
+                    let second_value = second.get(field).unwrap_or(&Value::Null);
+                    if first_value != second_value {
+                        println!("  {}: {} | {}", field, first_value, second_value);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn handle_command(config: Config, args: &MergeArgs) -> error::Result<()> {
+    let storage: Box<dyn DbStorage> = match &args.output {
+        Some(output) => storage::storage_for(output.as_str(), None),
+        None => storage::open_storage(&config),
+    };
+    // Locked before reading the copies, since one of them is usually the database itself
+    let _lock = storage.lock()?;
+    let first = storage::storage_for(args.first.as_str(), None).load()?;
+    let second = storage::storage_for(args.second.as_str(), None).load()?;
+
+    let mut unresolved: Vec<String> = Vec::new();
+    let (merged, summary) = merge::merge(&first, &second, |conflict| match args.on_conflict {
+        ConflictStrategy::First => Ok(Side::First),
+        ConflictStrategy::Second => Ok(Side::Second),
+        ConflictStrategy::Fail => {
+            unresolved.push(conflict_name(conflict));
+            Ok(Side::First)
+        }
+        ConflictStrategy::Ask => {
+            print_conflict(conflict)?;
+            match choose(
+                "Keep record from the first or the second copy?",
+                &["1", "2"],
+            )? {
+                0 => Ok(Side::First),
+                _ => Ok(Side::Second),
+            }
+        }
+    })?;
+    if !unresolved.is_empty() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Copies have conflicting records, nothing was merged: {}",
+            unresolved.join(", ")
+        ))));
+    }
+
+    storage.save(&merged)?;
+    println!("{}", summary);
+    println!("Saved merged database to: {}", storage.location());
+    Ok(())
+}
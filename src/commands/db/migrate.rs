@@ -0,0 +1,36 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{migrations, storage},
+    error::{self, Error, UserErrorKind},
+};
+
+#[derive(Parser, Debug)]
+pub struct MigrateArgs {}
+
+pub fn handle_command(config: Config, _args: &MigrateArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    if !storage.exists() {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Database not found at: {}",
+            storage.location()
+        ))));
+    }
+    let version = storage.schema_version()?;
+    if version == migrations::CURRENT_SCHEMA_VERSION {
+        println!(
+            "Database is already at the current schema version ({})",
+            version
+        );
+        return Ok(());
+    }
+
+    let _lock = storage.lock()?;
+    let tax_db = storage.load()?;
+    storage.save(&tax_db)?;
+    println!("Successfully migrated DB file: {}", storage.location());
+    Ok(())
+}
@@ -1,13 +1,35 @@
 extern crate clap;
 extern crate rust_decimal;
 
+mod backups;
+mod convert;
+mod decrypt;
+mod delete;
+mod due;
+mod edit;
+mod encrypt;
+mod export;
+mod import;
 mod insert;
+mod list;
 mod load;
+mod log;
+mod merge;
+mod migrate;
+mod pay;
+mod restore;
+mod show;
+mod verify;
 
 use crate::{config::Config, error};
 use clap::{AppSettings, Parser, Subcommand};
 
-use self::{insert::InsertArgs, load::LoadArgs};
+use self::{
+    backups::BackupsArgs, convert::ConvertArgs, decrypt::DecryptArgs, delete::DeleteArgs,
+    due::DueArgs, edit::EditArgs, encrypt::EncryptArgs, export::ExportArgs, import::ImportArgs,
+    insert::InsertArgs, list::ListArgs, load::LoadArgs, log::LogArgs, merge::MergeArgs,
+    migrate::MigrateArgs, pay::PayArgs, restore::RestoreArgs, show::ShowArgs, verify::VerifyArgs,
+};
 
 #[derive(Parser, Debug)]
 #[clap(setting(AppSettings::SubcommandRequiredElseHelp))]
@@ -22,11 +44,62 @@ enum DbCommands {
     Load(LoadArgs),
     #[clap(about = "Manually insert paid income tax data into the database")]
     Insert(InsertArgs),
+    #[clap(about = "List income tax entries in the database")]
+    List(ListArgs),
+    #[clap(about = "Show a single income tax entry from the database")]
+    Show(ShowArgs),
+    #[clap(about = "Edit an income tax entry, recalculating the tax")]
+    Edit(EditArgs),
+    #[clap(about = "Delete income tax entries from the database")]
+    Delete(DeleteArgs),
+    #[clap(about = "Export all income tax entries (CSV or JSON)")]
+    Export(ExportArgs),
+    #[clap(about = "Import income tax entries from a CSV file")]
+    Import(ImportArgs),
+    #[clap(about = "Upgrade database file to the current schema version")]
+    Migrate(MigrateArgs),
+    #[clap(about = "Convert database to another backend (JSON or SQLite)")]
+    Convert(ConvertArgs),
+    #[clap(about = "List automatic backups of the database")]
+    Backups(BackupsArgs),
+    #[clap(about = "Restore database from a backup")]
+    Restore(RestoreArgs),
+    #[clap(about = "Show history of changes made to the database")]
+    Log(LogArgs),
+    #[clap(about = "Mark tax of an income tax entry as paid")]
+    Pay(PayArgs),
+    #[clap(about = "List income tax entries which are not paid yet")]
+    Due(DueArgs),
+    #[clap(about = "Encrypt database with a passphrase")]
+    Encrypt(EncryptArgs),
+    #[clap(about = "Decrypt encrypted database")]
+    Decrypt(DecryptArgs),
+    #[clap(about = "Check the database for wrong, duplicate and missing entries")]
+    Verify(VerifyArgs),
+    #[clap(about = "Merge two copies of the database, resolving conflicting records")]
+    Merge(MergeArgs),
 }
 
 pub fn handle_command(config: Config, args: &DbArgs) -> error::Result<()> {
     match &args.command {
         DbCommands::Load(load_args) => load::handle_command(config, load_args),
         DbCommands::Insert(insert_args) => insert::handle_command(config, insert_args),
+        DbCommands::List(list_args) => list::handle_command(config, list_args),
+        DbCommands::Show(show_args) => show::handle_command(config, show_args),
+        DbCommands::Edit(edit_args) => edit::handle_command(config, edit_args),
+        DbCommands::Delete(delete_args) => delete::handle_command(config, delete_args),
+        DbCommands::Export(export_args) => export::handle_command(config, export_args),
+        DbCommands::Import(import_args) => import::handle_command(config, import_args),
+        DbCommands::Migrate(migrate_args) => migrate::handle_command(config, migrate_args),
+        DbCommands::Convert(convert_args) => convert::handle_command(config, convert_args),
+        DbCommands::Backups(backups_args) => backups::handle_command(config, backups_args),
+        DbCommands::Restore(restore_args) => restore::handle_command(config, restore_args),
+        DbCommands::Log(log_args) => log::handle_command(config, log_args),
+        DbCommands::Pay(pay_args) => pay::handle_command(config, pay_args),
+        DbCommands::Due(due_args) => due::handle_command(config, due_args),
+        DbCommands::Encrypt(encrypt_args) => encrypt::handle_command(config, encrypt_args),
+        DbCommands::Decrypt(decrypt_args) => decrypt::handle_command(config, decrypt_args),
+        DbCommands::Verify(verify_args) => verify::handle_command(config, verify_args),
+        DbCommands::Merge(merge_args) => merge::handle_command(config, merge_args),
     }
 }
@@ -0,0 +1,58 @@
+extern crate clap;
+
+use chrono::Local;
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    dates,
+    db::storage,
+    error::{self, Error, UserErrorKind},
+};
+
+#[derive(Parser, Debug)]
+pub struct PayArgs {
+    #[clap(index(1), help = "Id of the entry which was paid")]
+    id: u32,
+    #[clap(
+        long,
+        help = "Date the tax was paid on (YYYY-MM-DD). Defaults to today"
+    )]
+    date: Option<String>,
+    #[clap(long, help = "Reference of the tax payment")]
+    reference: Option<String>,
+}
+
+pub fn handle_command(config: Config, args: &PayArgs) -> error::Result<()> {
+    let paid_date = match &args.date {
+        Some(date) => dates::parse_date(date)?,
+        None => Local::today().naive_local(),
+    };
+
+    let storage = storage::open_storage(&config);
+    let _lock = storage.lock()?;
+    let mut entry = storage
+        .get_ams(args.id)?
+        .ok_or(Error::UserError(UserErrorKind::Generic(format!(
+            "No entry found with id: {}",
+            args.id
+        ))))?;
+    if entry.paid {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Entry {} is already marked as paid{}",
+            args.id,
+            entry
+                .paid_date
+                .as_ref()
+                .map(|date| format!(" on {}", date))
+                .unwrap_or_default()
+        ))));
+    }
+
+    entry.paid = true;
+    entry.paid_date = Some(paid_date.format(dates::DATE_FORMAT).to_string());
+    entry.payment_reference = args.reference.clone();
+    storage.update_ams(&entry)?;
+    println!("Marked entry {} as paid", args.id);
+    Ok(())
+}
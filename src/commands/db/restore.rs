@@ -0,0 +1,57 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    commands::confirm::confirm,
+    config::Config,
+    db::{
+        diff::DbDiff,
+        storage::{self, backup},
+        TaxDb,
+    },
+    error,
+};
+
+#[derive(Parser, Debug)]
+pub struct RestoreArgs {
+    #[clap(help = "Name of the backup (as listed by `db backups`) or path to backup file")]
+    backup: String,
+    #[clap(short, long, help = "Restore without asking for confirmation")]
+    yes: bool,
+}
+
+pub fn handle_command(config: Config, args: &RestoreArgs) -> error::Result<()> {
+    let storage = storage::open_storage(&config);
+    let backup = backup::find_backup(storage.location(), args.backup.as_str())?;
+    let _lock = storage.lock()?;
+    let tax_db = storage.load()?;
+    let backup_db = storage::storage_for(
+        backup.path.to_str().unwrap_or_default(),
+        Some(storage.backend()),
+    )
+    .load()?;
+
+    let diff = DbDiff::between(&tax_db, &backup_db);
+    if diff.is_empty() {
+        println!("Backup {} matches the current database", backup.name);
+        return Ok(());
+    }
+    println!("Restoring backup {} would make these changes:", backup.name);
+    println!("{}", diff);
+    if !confirm("Restore this backup?", args.yes)? {
+        println!("Aborted, database was not changed");
+        return Ok(());
+    }
+
+    // Ids assigned after the backup was made are still not reused
+    storage.save(&TaxDb {
+        next_ams_id: backup_db.next_ams_id.max(tax_db.next_ams_id),
+        ..backup_db
+    })?;
+    println!(
+        "Restored backup {}. Previous state of the database was backed up as well",
+        backup.name
+    );
+    Ok(())
+}
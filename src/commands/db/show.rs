@@ -0,0 +1,56 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{self, storage, TaxDb},
+    error::{self, Error, UserErrorKind},
+    format::printer::{CsvPrinter, JsonPrinter, Printer, TablePrinter, STDOUT_FILE},
+    format::OutputFormat,
+};
+
+#[derive(Parser, Debug)]
+pub struct ShowArgs {
+    #[clap(index(1), help = "Id of the entry")]
+    id: u32,
+    #[clap(long, help = "Output format (table, JSON, CSV)", default_value_t = OutputFormat::Table)]
+    output_format: OutputFormat,
+    #[clap(
+        short,
+        long,
+        help = "Path to save output file to (prints to stdout by default)",
+        default_value = STDOUT_FILE
+    )]
+    output: String,
+}
+
+pub fn handle_command(config: Config, args: &ShowArgs) -> error::Result<()> {
+    let columns: Vec<String> = db::AMS_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let json_printer = JsonPrinter::default();
+    let table_printer = TablePrinter {
+        columns: columns.clone(),
+    };
+    let csv_printer = CsvPrinter { columns };
+
+    let printer: &dyn Printer = match args.output_format {
+        OutputFormat::Table => &table_printer,
+        OutputFormat::Json => &json_printer,
+        OutputFormat::Csv => &csv_printer,
+        format => {
+            return Err(Error::UserError(UserErrorKind::UnsupportedOutputFormat(
+                format,
+            )))
+        }
+    };
+
+    let storage = storage::open_storage(&config);
+    let tax_db: TaxDb = storage.load()?;
+    let entry = tax_db
+        .get_ams(args.id)
+        .ok_or(Error::UserError(UserErrorKind::Generic(format!(
+            "No entry found with id: {}",
+            args.id
+        ))))?;
+    printer.write_to_file(entry.to_dict(), args.output.as_str())
+}
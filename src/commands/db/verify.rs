@@ -0,0 +1,85 @@
+extern crate clap;
+
+use chrono::Local;
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{
+        storage,
+        verify::{self, Severity},
+        TaxDb,
+    },
+    error::{self, Error, UserErrorKind},
+    format::printer::{CsvPrinter, JsonPrinter, ListPrinter, TablePrinter, STDOUT_FILE},
+    format::OutputFormat,
+};
+
+static VERIFY_COLUMNS: [&str; 3] = ["severity", "record", "problem"];
+
+#[derive(Parser, Debug)]
+pub struct VerifyArgs {
+    #[clap(long, help = "Fail on warnings too, not only on errors")]
+    strict: bool,
+    #[clap(long, help = "Output format (table, JSON, CSV)", default_value_t = OutputFormat::Table)]
+    output_format: OutputFormat,
+    #[clap(
+        short,
+        long,
+        help = "Path to save output file to (prints to stdout by default)",
+        default_value = STDOUT_FILE
+    )]
+    output: String,
+}
+
+pub fn handle_command(config: Config, args: &VerifyArgs) -> error::Result<()> {
+    let columns: Vec<String> = VERIFY_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let json_printer = JsonPrinter::default();
+    let table_printer = TablePrinter {
+        columns: columns.clone(),
+    };
+    let csv_printer = CsvPrinter { columns };
+
+    let printer: &dyn ListPrinter = match args.output_format {
+        OutputFormat::Table => &table_printer,
+        OutputFormat::Json => &json_printer,
+        OutputFormat::Csv => &csv_printer,
+        format => {
+            return Err(Error::UserError(UserErrorKind::UnsupportedOutputFormat(
+                format,
+            )))
+        }
+    };
+
+    let storage = storage::open_storage(&config);
+    let tax_db: TaxDb = storage.load()?;
+    let problems = verify::verify(&tax_db, Local::today().naive_local());
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    let warnings = problems.len() - errors;
+    // Empty report is written too, unless it would be just a table header
+    if !problems.is_empty()
+        || args.output != STDOUT_FILE
+        || args.output_format != OutputFormat::Table
+    {
+        printer.write_list_to_file(
+            problems.iter().map(|problem| problem.to_dict()).collect(),
+            None,
+            args.output.as_str(),
+        )?;
+    }
+    if problems.is_empty() {
+        println!("No problems found in {} entries", tax_db.ams.len());
+        return Ok(());
+    }
+    if errors > 0 || (args.strict && warnings > 0) {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Found {} errors and {} warnings",
+            errors, warnings
+        ))));
+    }
+    println!("Found {} warnings", warnings);
+    Ok(())
+}
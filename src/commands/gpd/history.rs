@@ -0,0 +1,54 @@
+extern crate clap;
+
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    db::{self, storage, GpdInfo, TaxDb},
+    error,
+    forms::formutils::format_money_value,
+};
+
+#[derive(Parser, Debug)]
+pub struct HistoryArgs {
+    #[clap(
+        long,
+        help = "Show only GPD forms of this taxpayer (profile id or JMBG)"
+    )]
+    taxpayer: Option<String>,
+}
+
+pub fn handle_command(config: Config, args: &HistoryArgs) -> error::Result<()> {
+    let taxpayer = config.taxpayer_id(args.taxpayer.as_ref())?;
+    let storage = storage::open_storage(&config);
+    let tax_db: TaxDb = storage.load()?;
+    let mut results: Vec<(&String, &GpdInfo)> = tax_db
+        .gpd
+        .iter()
+        .filter(|(_, gpd_info)| taxpayer.is_none() || gpd_info.taxpayer == taxpayer)
+        .collect();
+    if results.is_empty() {
+        println!("No GPD forms stored in the database");
+        return Ok(());
+    }
+
+    results.sort_by_key(|(key, _)| *key);
+    println!(
+        "{:<6} {:<12} {:>12} {:>12} {:>12} {:>12} {:>12}  Output file",
+        "Year", "Taxpayer", "Income", "Tax base", "Tax due", "Refund", "Loss"
+    );
+    for (key, gpd_info) in results {
+        println!(
+            "{:<6} {:<12} {:>12} {:>12} {:>12} {:>12} {:>12}  {}",
+            db::gpd_year(key),
+            gpd_info.taxpayer.clone().unwrap_or_default(),
+            format_money_value(gpd_info.income_total),
+            format_money_value(gpd_info.tax_base),
+            format_money_value(gpd_info.tax_due),
+            format_money_value(gpd_info.refund),
+            format_money_value(gpd_info.loss),
+            gpd_info.output_file.clone().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
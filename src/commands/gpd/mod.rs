@@ -1,30 +1,52 @@
 extern crate clap;
 extern crate rust_decimal;
 
+mod history;
+
 use std::{fs::File, path::Path};
 
 use crate::{
     config::{self, Config, UserConfig},
-    db::{self, TaxDb},
+    dates::{self, Period},
+    db::{storage, GpdInfo, TaxDb},
     error::{self, Error, UserErrorKind},
-    format::printer::{FdfPrinter, JsonPrinter, PdfPrinter, Printer, XfdfPrinter},
+    format::printer::{
+        FdfPrinter, JsonPrinter, NativePdfPrinter, PdfBackend, PdftkPrinter, Printer, XfdfPrinter,
+    },
     format::OutputFormat,
     forms::gpdform::{self, FormField},
+    taxcalculator,
 };
-use clap::Parser;
+use chrono::{Datelike, NaiveDate};
+use clap::{AppSettings, Parser, Subcommand};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 
+use self::history::HistoryArgs;
+
 #[derive(Parser, Debug)]
+#[clap(setting(AppSettings::SubcommandsNegateReqs))]
+#[clap(setting(AppSettings::ArgsNegateSubcommands))]
 pub struct GpdArgs {
-    #[clap(long, help = "Year (YYYY)")]
-    year: String,
+    #[clap(subcommand)]
+    command: Option<GpdCommands>,
+    #[clap(long, help = "Year (YYYY)", required = true)]
+    year: Option<String>,
+    #[clap(
+        long,
+        help = "Start of the tax period (YYYY-MM-DD), if tax residency started during the year. Defaults to the start of the year"
+    )]
+    period_start: Option<String>,
     #[clap(
         long,
-        help = "Personal deduction (by default it is 300 for each months => 300 * 12 = 3600)",
-        default_value_t = dec!(3600)
+        help = "End of the tax period (YYYY-MM-DD), if tax residency ended during the year. Defaults to the end of the year"
     )]
-    personal_deduction: Decimal,
+    period_end: Option<String>,
+    #[clap(
+        long,
+        help = "Personal deduction (by default it is 300 for each month of the tax period => 300 * 12 = 3600 for the whole year)"
+    )]
+    personal_deduction: Option<Decimal>,
     #[clap(
         long,
         help = "Sum of 11th column of GIP form (tax base)",
@@ -37,10 +59,79 @@ pub struct GpdArgs {
         default_value_t = dec!(0)
     )]
     gip_tax_paid: Decimal,
+    #[clap(
+        long,
+        help = "Income from agricultural activity",
+        default_value_t = dec!(0)
+    )]
+    agro_income: Decimal,
+    #[clap(
+        long,
+        help = "Loss from agricultural activity",
+        default_value_t = dec!(0)
+    )]
+    agro_expense: Decimal,
+    #[clap(
+        long,
+        help = "Taxes paid in advance for income from agricultural activity",
+        default_value_t = dec!(0)
+    )]
+    agro_tax_paid: Decimal,
+    #[clap(
+        long,
+        help = "Income from property rental",
+        default_value_t = dec!(0)
+    )]
+    rent_income: Decimal,
+    #[clap(
+        long,
+        help = "Loss from property rental",
+        default_value_t = dec!(0)
+    )]
+    rent_expense: Decimal,
+    #[clap(
+        long,
+        help = "Taxes paid in advance for income from property rental",
+        default_value_t = dec!(0)
+    )]
+    rent_tax_paid: Decimal,
+    #[clap(
+        long,
+        help = "Income from property rights",
+        default_value_t = dec!(0)
+    )]
+    rent2_income: Decimal,
+    #[clap(
+        long,
+        help = "Loss from property rights",
+        default_value_t = dec!(0)
+    )]
+    rent2_expense: Decimal,
+    #[clap(
+        long,
+        help = "Taxes paid in advance for income from property rights",
+        default_value_t = dec!(0)
+    )]
+    rent2_tax_paid: Decimal,
+    #[clap(
+        long,
+        help = "Unused loss from previous years to carry forward (by default it is calculated from GPD results stored in the database)"
+    )]
+    previous_loss: Option<Decimal>,
     #[clap(long, help = "Output format (PDF, FDF, XFDF, JSON)", default_value_t = OutputFormat::Pdf)]
     output_format: OutputFormat,
+    #[clap(
+        long,
+        help = "Font for PDF output text fields (path to TTF file, pdftk backend also accepts font names). Overrides pdf.replacementFont from configuration"
+    )]
+    font: Option<String>,
     #[clap(long, help = "Path to config file with user specific settings")]
     user_config: Option<String>,
+    #[clap(
+        long,
+        help = "Taxpayer profile id or JMBG. Only income of this taxpayer is included and results are stored for them"
+    )]
+    taxpayer: Option<String>,
     #[clap(
         short,
         long,
@@ -48,9 +139,48 @@ pub struct GpdArgs {
         default_value = "gpdform.pdf"
     )]
     output: String,
+    #[clap(
+        long,
+        help = "By default DB file is updated with results of this GPD form. Add this flag to skip writing to db"
+    )]
+    skip_db: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum GpdCommands {
+    #[clap(about = "List GPD forms stored in the database")]
+    History(HistoryArgs),
 }
 
 pub fn handle_command(config: Config, args: &GpdArgs) -> error::Result<()> {
+    match &args.command {
+        Some(GpdCommands::History(history_args)) => history::handle_command(config, history_args),
+        None => generate_gpd_form(config, args),
+    }
+}
+
+fn generate_gpd_form(config: Config, args: &GpdArgs) -> error::Result<()> {
+    if args.font.is_some() && args.output_format != OutputFormat::Pdf {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "--font can only be used with PDF output format, not {}",
+            args.output_format
+        ))));
+    }
+    let year = args
+        .year
+        .clone()
+        .ok_or(Error::UserError(UserErrorKind::MissingConfig(
+            "year".to_string(),
+            "--year".to_string(),
+        )))?;
+    let (period_start, period_end) = parse_period(&year, args)?;
+    let taxpayer = args
+        .taxpayer
+        .as_deref()
+        .map(|taxpayer| config.taxpayer(taxpayer))
+        .transpose()?;
+    let taxpayer_id = taxpayer.as_ref().map(|(id, _)| id.clone());
+
     if !Path::new(config.gpd.cache_location.as_str()).exists() {
         println!(
             "Cached GPD form not found at: {}\nResorting to download from: {}",
@@ -66,19 +196,47 @@ pub fn handle_command(config: Config, args: &GpdArgs) -> error::Result<()> {
     }
 
     let mut form = gpdform::load_gpd_form(config.gpd.cache_location.clone())?;
-    let db: TaxDb = db::parse_db_with_default(config.db_location.as_str());
+    let storage = storage::open_storage(&config);
+    let db: TaxDb = storage.load()?;
+    // Warnings are printed to stderr, so they don't end up in the form written to stdout
+    if let Some(existing) = db.get_gpd(&year, taxpayer_id.as_deref()) {
+        eprintln!(
+            "Warning: GPD form for year {} was already generated{}. Its stored results will be replaced",
+            year,
+            existing
+                .output_file
+                .as_ref()
+                .map(|file| format!(" ({})", file))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(warning) = db.malformed_dates_warning() {
+        eprintln!("{}", warning);
+    }
 
     let fdf_printer = FdfPrinter {};
     let xfdf_printer = XfdfPrinter {};
     let json_printer = JsonPrinter::default();
-    let pdf_printer = PdfPrinter {
+    let font = args
+        .font
+        .clone()
+        .or_else(|| config.pdf.replacement_font.clone());
+    let native_pdf_printer = NativePdfPrinter {
+        source_pdf: config.gpd.cache_location.clone(),
+        font: font.clone(),
+    };
+    let pdftk_printer = PdftkPrinter {
         config: &config,
         source_pdf: config.gpd.cache_location.clone(),
         xfdf_printer: &xfdf_printer,
+        font,
     };
 
     let printer: &dyn Printer = match args.output_format {
-        OutputFormat::Pdf => &pdf_printer,
+        OutputFormat::Pdf => match config.pdf.backend {
+            PdfBackend::Native => &native_pdf_printer,
+            PdfBackend::Pdftk => &pdftk_printer,
+        },
         OutputFormat::Fdf => &fdf_printer,
         OutputFormat::Xfdf => &xfdf_printer,
         OutputFormat::Json => &json_printer,
@@ -89,28 +247,50 @@ pub fn handle_command(config: Config, args: &GpdArgs) -> error::Result<()> {
         }
     };
 
-    let user_config = match &args.user_config {
-        Some(path) => config::parse_config::<UserConfig>(path.as_str())?,
-        None => config
-            .user
-            .clone()
-            .ok_or(Error::UserError(UserErrorKind::MissingConfig(
-                "user configuration".to_string(),
-                "--user-config".to_string(),
-            )))?,
+    let user_config = match (&args.user_config, &taxpayer) {
+        (Some(path), _) => config::parse_config::<UserConfig>(path.as_str())?,
+        (None, Some((_, user))) => user.clone(),
+        (None, None) => {
+            config
+                .user
+                .clone()
+                .ok_or(Error::UserError(UserErrorKind::MissingConfig(
+                    "user configuration".to_string(),
+                    "--user-config".to_string(),
+                )))?
+        }
     };
     form.fill_user_info(&user_config)?;
-    form.fill_year_info(args.year.clone())?;
-    form.fill_field(
-        FormField::PersonalDeduction,
-        args.personal_deduction.to_string(),
-    )?;
+    form.fill_year_info(year.clone(), period_start, period_end)?;
+    let personal_deduction = args.personal_deduction.unwrap_or_else(|| {
+        taxcalculator::personal_deduction(dates::months_in_period(period_start, period_end))
+    });
+    form.fill_field(FormField::PersonalDeduction, personal_deduction.to_string())?;
     form.add_gip_info(args.gip_income, args.gip_tax_paid);
-    form.add_deductions(args.personal_deduction, dec!(0), dec!(0));
+    if !args.agro_income.is_zero() || !args.agro_expense.is_zero() || !args.agro_tax_paid.is_zero()
+    {
+        form.add_agro_info(args.agro_income, args.agro_expense, args.agro_tax_paid);
+    }
+    if !args.rent_income.is_zero() || !args.rent_expense.is_zero() || !args.rent_tax_paid.is_zero()
+    {
+        form.add_rent_info(args.rent_income, args.rent_expense, args.rent_tax_paid);
+    }
+    if !args.rent2_income.is_zero()
+        || !args.rent2_expense.is_zero()
+        || !args.rent2_tax_paid.is_zero()
+    {
+        form.add_rent2_info(args.rent2_income, args.rent2_expense, args.rent2_tax_paid);
+    }
+    form.add_deductions(personal_deduction, dec!(0), dec!(0));
+    let period = Period::Range(period_start, period_end);
     form.add_ams_info(
-        db.total_income_for_year(args.year.clone()),
-        db.total_tax_paid_for_year(args.year.clone()),
+        db.total_income(&period, taxpayer_id.as_deref()),
+        db.total_tax_paid(&period, taxpayer_id.as_deref()),
     );
+    let previous_loss = args
+        .previous_loss
+        .unwrap_or_else(|| db.carried_forward_loss(period_start.year(), taxpayer_id.as_deref()));
+    form.add_previous_loss(previous_loss);
 
     let output_path = Path::new(config.output_location.as_str());
     let mut output_file_path = output_path.join(args.output.clone());
@@ -123,7 +303,60 @@ pub fn handle_command(config: Config, args: &GpdArgs) -> error::Result<()> {
                 "Output location seems to be invalid!".to_string(),
             )))?;
 
-    printer.write_to_file(form.to_dict()?, output_file_path_str)?;
+    printer.write_form_to_file(&mut form, output_file_path_str)?;
     println!("Saved GPD form to: {}", output_file_path_str);
+
+    if !args.skip_db {
+        let gpd_info = GpdInfo {
+            taxpayer: taxpayer_id,
+            period_start: Some(period_start.format(dates::DATE_FORMAT).to_string()),
+            period_end: Some(period_end.format(dates::DATE_FORMAT).to_string()),
+            output_file: Some(output_file_path_str.to_string()),
+            ..form.get_gpd_info()
+        };
+        write_to_db(&config, gpd_info, year)?;
+    }
     Ok(())
 }
+
+fn write_to_db(config: &Config, gpd_info: GpdInfo, year: String) -> error::Result<()> {
+    println!("Loading database file");
+    let storage = storage::open_storage(config);
+    let _lock = storage.lock()?;
+    storage.save_gpd(gpd_info, year)?;
+    println!(
+        "Successfully updated DB file: {}",
+        config.db_location.as_str(),
+    );
+    Ok(())
+}
+
+fn parse_period(year: &str, args: &GpdArgs) -> error::Result<(NaiveDate, NaiveDate)> {
+    let invalid_year = || {
+        Error::UserError(UserErrorKind::Generic(format!(
+            "Invalid year: {}. Expected format is YYYY",
+            year
+        )))
+    };
+    let year: i32 = year.parse().map_err(|_| invalid_year())?;
+    let start = match &args.period_start {
+        Some(date) => dates::parse_date(date)?,
+        None => dates::year_start(year).ok_or_else(invalid_year)?,
+    };
+    let end = match &args.period_end {
+        Some(date) => dates::parse_date(date)?,
+        None => dates::year_end(year).ok_or_else(invalid_year)?,
+    };
+    if start.year() != year || end.year() != year {
+        return Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Tax period must be within year {}",
+            year
+        ))));
+    }
+    if start > end {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Tax period start must not be after its end".to_string(),
+        )));
+    }
+    Ok((start, end))
+}
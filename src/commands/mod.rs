@@ -1,4 +1,5 @@
 pub mod ams;
+mod confirm;
 pub mod db;
 pub mod gpd;
 pub mod taxbreakdown;
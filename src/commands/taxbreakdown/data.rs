@@ -13,11 +13,11 @@ pub struct TaxBreakdownData {
 
 impl TaxBreakdownData {
     fn get_health_insurance_total(&self) -> Decimal {
-        return self.health_insurance_federation + self.health_insurance_canton;
+        self.health_insurance_federation + self.health_insurance_canton
     }
 
     fn get_total(&self) -> Decimal {
-        return self.income_tax + self.get_health_insurance_total();
+        self.income_tax + self.get_health_insurance_total()
     }
 
     pub fn to_dict(&self) -> HashMap<String, String> {
@@ -42,6 +42,6 @@ impl TaxBreakdownData {
             "total".to_string(),
             self.get_total().round_dp(2).to_string(),
         );
-        return result;
+        result
     }
 }
@@ -81,33 +81,27 @@ pub fn handle_command(config: Config, args: &TaxBreakdownArgs) -> error::Result<
     )
     .to_string();
 
-    match args.output_template.clone() {
-        Some(template) => {
-            stdout_template = template.clone();
-            json_formatter = Box::new(
-                move |data: HashMap<String, String>| -> error::Result<serde_json::Value> {
-                    let result = fill_template(template.clone(), data);
-                    serde_json::from_str(result.as_str()).map_err(Error::from)
-                },
-            );
-        }
-        None => {}
-    };
-    match args.output_template_file.clone() {
-        Some(template_file_path) => {
-            let mut template_file = File::open(template_file_path.clone())?;
-            let mut template = String::new();
-            template_file.read_to_string(&mut template)?;
-            stdout_template = template.clone();
-            json_formatter = Box::new(
-                move |data: HashMap<String, String>| -> error::Result<serde_json::Value> {
-                    let result = fill_template(template.clone(), data);
-                    serde_json::from_str(result.as_str()).map_err(Error::from)
-                },
-            );
-        }
-        None => {}
-    };
+    if let Some(template) = args.output_template.clone() {
+        stdout_template = template.clone();
+        json_formatter = Box::new(
+            move |data: HashMap<String, String>| -> error::Result<serde_json::Value> {
+                let result = fill_template(template.clone(), data);
+                serde_json::from_str(result.as_str()).map_err(Error::from)
+            },
+        );
+    }
+    if let Some(template_file_path) = args.output_template_file.clone() {
+        let mut template_file = File::open(template_file_path.clone())?;
+        let mut template = String::new();
+        template_file.read_to_string(&mut template)?;
+        stdout_template = template.clone();
+        json_formatter = Box::new(
+            move |data: HashMap<String, String>| -> error::Result<serde_json::Value> {
+                let result = fill_template(template.clone(), data);
+                serde_json::from_str(result.as_str()).map_err(Error::from)
+            },
+        );
+    }
     let json_printer = JsonPrinter { json_formatter };
 
     let stdout_printer = StdoutPrinter {
@@ -1,9 +1,13 @@
 extern crate serde;
 extern crate serde_json;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{fs::File, io::BufReader};
+use std::{collections::HashMap, fs::File, io::BufReader};
 
-use crate::error::{Error, Result};
+use crate::{
+    db::storage::DbBackend,
+    error::{Error, Result, UserErrorKind},
+    format::printer::PdfBackend,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -18,10 +22,50 @@ pub struct Config {
     pub output_location: String,
     #[serde(default = "default_db_location")]
     pub db_location: String,
+    /// Database backend. When not set, it is picked based on `db_location` extension
+    #[serde(default)]
+    pub db_backend: Option<DbBackend>,
+    /// Number of database backups to keep. Backups are disabled when set to 0
+    #[serde(default = "default_backup_count")]
+    pub backup_count: usize,
     pub user: Option<UserConfig>,
+    /// Taxpayer profiles by their ids, for households filing for multiple taxpayers
+    #[serde(default)]
+    pub taxpayers: HashMap<String, UserConfig>,
     pub client: Option<ClientConfig>,
 }
 
+impl Config {
+    /// Finds taxpayer by profile id or JMBG. Returns the id records of the taxpayer are
+    /// tagged with (profile id, or JMBG of the default user) and the taxpayer's details.
+    pub fn taxpayer(&self, taxpayer: &str) -> Result<(String, UserConfig)> {
+        if let Some(user) = self.taxpayers.get(taxpayer) {
+            return Ok((taxpayer.to_string(), user.clone()));
+        }
+        if let Some((id, user)) = self.taxpayers.iter().find(|(_, u)| u.jmbg == taxpayer) {
+            return Ok((id.clone(), user.clone()));
+        }
+        match &self.user {
+            Some(user) if user.jmbg == taxpayer => Ok((taxpayer.to_string(), user.clone())),
+            _ => Err(Error::UserError(UserErrorKind::Generic(format!(
+                "Unknown taxpayer: {}. Add its profile to `taxpayers` in the config file",
+                taxpayer
+            )))),
+        }
+    }
+
+    /// Id of the selected taxpayer, if any
+    pub fn taxpayer_id(&self, taxpayer: Option<&String>) -> Result<Option<String>> {
+        taxpayer
+            .map(|taxpayer| self.taxpayer(taxpayer).map(|(id, _)| id))
+            .transpose()
+    }
+}
+
+fn default_backup_count() -> usize {
+    10
+}
+
 fn default_output_location() -> String {
     ".".to_string()
 }
@@ -33,8 +77,15 @@ fn default_db_location() -> String {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PdfConfig {
+    /// Backend used to produce PDF forms. pdftk is only needed when it is set to `pdftk`
+    #[serde(default = "default_pdf_backend")]
+    pub backend: PdfBackend,
     #[serde(default = "default_pdftk_path")]
     pub pdftk_path: String,
+    /// Font used for text fields of PDF forms. Native backend needs path to a TTF file,
+    /// while pdftk also accepts font names
+    #[serde(default)]
+    pub replacement_font: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -90,6 +141,10 @@ fn default_gpd_download_url() -> String {
         .to_string()
 }
 
+fn default_pdf_backend() -> PdfBackend {
+    PdfBackend::Native
+}
+
 fn default_pdftk_path() -> String {
     "pdftk".to_string()
 }
@@ -97,7 +152,9 @@ fn default_pdftk_path() -> String {
 impl Default for PdfConfig {
     fn default() -> Self {
         Self {
+            backend: default_pdf_backend(),
             pdftk_path: default_pdftk_path(),
+            replacement_font: None,
         }
     }
 }
@@ -125,10 +182,13 @@ impl Default for Config {
         Self {
             output_location: default_output_location(),
             db_location: default_db_location(),
+            db_backend: None,
+            backup_count: default_backup_count(),
             pdf: Default::default(),
             ams: AmsConfig::default(),
             gpd: GpdConfig::default(),
             user: None,
+            taxpayers: HashMap::new(),
             client: None,
         }
     }
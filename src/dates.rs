@@ -0,0 +1,156 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::error::{Error, Result, UserErrorKind};
+
+pub static DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Parses date in YYYY-MM-DD format. Dates with short years or without zero padding
+/// are rejected, since they don't sort correctly with other dates.
+pub fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+        .ok()
+        .filter(|parsed| parsed.format(DATE_FORMAT).to_string() == date)
+        .ok_or_else(|| {
+            Error::UserError(UserErrorKind::Generic(format!(
+                "Invalid date: {}. Expected format is YYYY-MM-DD",
+                date
+            )))
+        })
+}
+
+pub fn year_start(year: i32) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(year, 1, 1)
+}
+
+pub fn year_end(year: i32) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(year, 12, 31)
+}
+
+pub fn month_start(year: i32, month: u32) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(year, month, 1)
+}
+
+pub fn month_end(year: i32, month: u32) -> Option<NaiveDate> {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1).and_then(|date| date.pred_opt())
+}
+
+/// Period of time entries can be queried for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Year(i32),
+    /// Year and quarter (1-4)
+    Quarter(i32, u32),
+    /// Year and month (1-12)
+    Month(i32, u32),
+    /// Dates between start and end, both included
+    Range(NaiveDate, NaiveDate),
+}
+
+impl Period {
+    /// First and last day of the period, if the period is valid
+    pub fn bounds(&self) -> Option<(NaiveDate, NaiveDate)> {
+        match *self {
+            Period::Year(year) => year_start(year).zip(year_end(year)),
+            Period::Quarter(year, quarter) if (1..=4).contains(&quarter) => {
+                month_start(year, quarter * 3 - 2).zip(month_end(year, quarter * 3))
+            }
+            Period::Quarter(_, _) => None,
+            Period::Month(year, month) => month_start(year, month).zip(month_end(year, month)),
+            Period::Range(start, end) => Some((start, end)),
+        }
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.bounds()
+            .map(|(start, end)| date >= start && date <= end)
+            .unwrap_or(false)
+    }
+}
+
+/// Number of calendar months touched by the period, including partial months
+pub fn months_in_period(start: NaiveDate, end: NaiveDate) -> u32 {
+    if end < start {
+        return 0;
+    }
+    let start_months = start.year() * 12 + start.month0() as i32;
+    let end_months = end.year() * 12 + end.month0() as i32;
+    (end_months - start_months + 1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_date_test() {
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 3, 15),
+            parse_date("2021-03-15").unwrap()
+        );
+        assert!(parse_date("2021-13-01").is_err());
+        assert!(parse_date("15.03.2021").is_err());
+        assert!(parse_date("2021-3-15").is_err());
+        assert!(parse_date("21-03-15").is_err());
+        assert!(parse_date("").is_err());
+    }
+
+    #[test]
+    fn period_bounds_test() {
+        let pairs = [
+            (Period::Year(2021), Some(((2021, 1, 1), (2021, 12, 31)))),
+            (
+                Period::Quarter(2021, 1),
+                Some(((2021, 1, 1), (2021, 3, 31))),
+            ),
+            (
+                Period::Quarter(2021, 4),
+                Some(((2021, 10, 1), (2021, 12, 31))),
+            ),
+            (Period::Quarter(2021, 5), None),
+            (Period::Month(2020, 2), Some(((2020, 2, 1), (2020, 2, 29)))),
+            (
+                Period::Month(2021, 12),
+                Some(((2021, 12, 1), (2021, 12, 31))),
+            ),
+            (Period::Month(2021, 13), None),
+        ];
+
+        for (period, expected) in pairs {
+            assert_eq!(
+                expected.map(|((sy, sm, sd), (ey, em, ed))| (
+                    NaiveDate::from_ymd(sy, sm, sd),
+                    NaiveDate::from_ymd(ey, em, ed)
+                )),
+                period.bounds()
+            );
+        }
+        assert!(Period::Quarter(2021, 2).contains(NaiveDate::from_ymd(2021, 6, 30)));
+        assert!(!Period::Quarter(2021, 2).contains(NaiveDate::from_ymd(2021, 7, 1)));
+    }
+
+    #[test]
+    fn months_in_period_test() {
+        let pairs = [
+            ((2021, 1, 1), (2021, 12, 31), 12),
+            ((2021, 3, 15), (2021, 12, 31), 10),
+            ((2021, 1, 1), (2021, 6, 1), 6),
+            ((2021, 5, 10), (2021, 5, 20), 1),
+            ((2021, 6, 1), (2021, 5, 31), 0),
+        ];
+
+        for ((sy, sm, sd), (ey, em, ed), expected) in pairs {
+            assert_eq!(
+                expected,
+                months_in_period(
+                    NaiveDate::from_ymd(sy, sm, sd),
+                    NaiveDate::from_ymd(ey, em, ed)
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Mutex;
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{Error, Result, UserErrorKind};
+
+/// Environment variable the database passphrase is read from, before prompting for it
+pub static PASSPHRASE_ENV: &str = "FBIHTAX_DB_PASSPHRASE";
+
+static ENVELOPE_VERSION: u32 = 1;
+static KDF: &str = "argon2id";
+static SALT_LENGTH: usize = 16;
+
+/// Passphrase is asked for only once per run
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+/// Keys derived from passphrases and salts, since deriving them is slow on purpose
+#[allow(clippy::type_complexity)]
+static KEYS: Mutex<Vec<(String, String, [u8; 32])>> = Mutex::new(Vec::new());
+
+/// Encrypted data, stored as JSON
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    fbihtax_encrypted: u32,
+    kdf: String,
+    pub salt: String,
+    nonce: String,
+    data: String,
+}
+
+pub fn is_envelope(value: &Value) -> bool {
+    value.get("fbihtaxEncrypted").is_some()
+}
+
+/// Passphrase from the environment, or from the user.
+/// When `confirm` is set, user has to enter new passphrase twice.
+pub fn passphrase(confirm: bool) -> Result<String> {
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if let Some(passphrase) = cached.as_ref() {
+        return Ok(passphrase.clone());
+    }
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let missing_passphrase = |_| {
+                Error::UserError(UserErrorKind::MissingConfig(
+                    "database passphrase".to_string(),
+                    PASSPHRASE_ENV.to_string(),
+                ))
+            };
+            let passphrase =
+                rpassword::prompt_password("Database passphrase: ").map_err(missing_passphrase)?;
+            if confirm
+                && rpassword::prompt_password("Repeat database passphrase: ")
+                    .map_err(missing_passphrase)?
+                    != passphrase
+            {
+                return Err(Error::UserError(UserErrorKind::Generic(
+                    "Passphrases do not match".to_string(),
+                )));
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Database passphrase must not be empty".to_string(),
+        )));
+    }
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    base64::decode(value).map_err(|err| {
+        Error::UnexpectedCondition(format!("Invalid encrypted database contents: {}", err))
+    })
+}
+
+fn derive_key(passphrase: &str, salt: &str) -> Result<[u8; 32]> {
+    let mut keys = KEYS.lock().unwrap();
+    if let Some((_, _, key)) = keys
+        .iter()
+        .find(|(key_passphrase, key_salt, _)| key_passphrase == passphrase && key_salt == salt)
+    {
+        return Ok(*key);
+    }
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &decode(salt)?, &mut key)
+        .map_err(|err| Error::UnexpectedCondition(format!("Failed to derive key: {}", err)))?;
+    keys.push((passphrase.to_string(), salt.to_string(), key));
+    Ok(key)
+}
+
+pub fn new_salt() -> String {
+    let mut salt = vec![0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    base64::encode(salt)
+}
+
+fn encrypt_with_passphrase(plaintext: &[u8], passphrase: &str, salt: &str) -> Result<Envelope> {
+    let key = derive_key(passphrase, salt)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let data = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| Error::UnexpectedCondition("Failed to encrypt database".to_string()))?;
+    Ok(Envelope {
+        fbihtax_encrypted: ENVELOPE_VERSION,
+        kdf: KDF.to_string(),
+        salt: salt.to_string(),
+        nonce: base64::encode(nonce),
+        data: base64::encode(data),
+    })
+}
+
+fn decrypt_with_passphrase(envelope: &Envelope, passphrase: &str) -> Result<Vec<u8>> {
+    if envelope.fbihtax_encrypted != ENVELOPE_VERSION || envelope.kdf != KDF {
+        return Err(Error::UnexpectedCondition(format!(
+            "Unsupported database encryption: version {}, {}",
+            envelope.fbihtax_encrypted, envelope.kdf
+        )));
+    }
+    let key = derive_key(passphrase, envelope.salt.as_str())?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = decode(envelope.nonce.as_str())?;
+    cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            decode(envelope.data.as_str())?.as_ref(),
+        )
+        .map_err(|_| {
+            Error::UserError(UserErrorKind::Generic(
+                "Failed to decrypt database. Check the passphrase".to_string(),
+            ))
+        })
+}
+
+/// Encrypts data with a key derived from the passphrase and the given salt
+pub fn encrypt(plaintext: &[u8], salt: &str) -> Result<Envelope> {
+    encrypt_with_passphrase(plaintext, passphrase(false)?.as_str(), salt)
+}
+
+pub fn decrypt(envelope: &Envelope) -> Result<Vec<u8>> {
+    decrypt_with_passphrase(envelope, passphrase(false)?.as_str())
+}
+
+/// Decrypts JSON value if it is encrypted, or returns it as it is
+pub fn decrypt_value(value: Value) -> Result<Value> {
+    if !is_envelope(&value) {
+        return Ok(value);
+    }
+    let envelope: Envelope = serde_json::from_value(value)?;
+    serde_json::from_slice(&decrypt(&envelope)?).map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt_test() {
+        let salt = new_salt();
+        let envelope = encrypt_with_passphrase(b"{\"ams\": []}", "secret", &salt).unwrap();
+        assert_eq!(salt, envelope.salt);
+        assert!(is_envelope(&serde_json::to_value(&envelope).unwrap()));
+
+        assert_eq!(
+            b"{\"ams\": []}".to_vec(),
+            decrypt_with_passphrase(&envelope, "secret").unwrap()
+        );
+        assert!(decrypt_with_passphrase(&envelope, "wrong").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use serde::Serialize;
+
+use super::TaxDb;
+
+/// Differences between two states of the database, from the old state to the new one
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DbDiff {
+    pub ams_added: Vec<u32>,
+    pub ams_removed: Vec<u32>,
+    pub ams_changed: Vec<u32>,
+    pub gpd_added: Vec<String>,
+    pub gpd_removed: Vec<String>,
+    pub gpd_changed: Vec<String>,
+}
+
+fn same_value<T: Serialize>(old: &T, new: &T) -> bool {
+    serde_json::to_value(old).ok() == serde_json::to_value(new).ok()
+}
+
+impl DbDiff {
+    pub fn between(old: &TaxDb, new: &TaxDb) -> DbDiff {
+        let mut diff = DbDiff::default();
+
+        let ams_ids: BTreeSet<u32> = old.ams.iter().chain(new.ams.iter()).map(|a| a.id).collect();
+        for id in ams_ids {
+            match (old.get_ams(id), new.get_ams(id)) {
+                (None, Some(_)) => diff.ams_added.push(id),
+                (Some(_), None) => diff.ams_removed.push(id),
+                (Some(old_info), Some(new_info)) if !same_value(old_info, new_info) => {
+                    diff.ams_changed.push(id)
+                }
+                _ => {}
+            }
+        }
+
+        let gpd_years: BTreeSet<&String> = old.gpd.keys().chain(new.gpd.keys()).collect();
+        for year in gpd_years {
+            match (old.gpd.get(year), new.gpd.get(year)) {
+                (None, Some(_)) => diff.gpd_added.push(year.clone()),
+                (Some(_), None) => diff.gpd_removed.push(year.clone()),
+                (Some(old_info), Some(new_info)) if !same_value(old_info, new_info) => {
+                    diff.gpd_changed.push(year.clone())
+                }
+                _ => {}
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == DbDiff::default()
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Display for DbDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("No changes");
+        }
+        let lines = [
+            ("AMS entries added", join(&self.ams_added)),
+            ("AMS entries removed", join(&self.ams_removed)),
+            ("AMS entries changed", join(&self.ams_changed)),
+            ("GPD years added", join(&self.gpd_added)),
+            ("GPD years removed", join(&self.gpd_removed)),
+            ("GPD years changed", join(&self.gpd_changed)),
+        ];
+        let lines: Vec<String> = lines
+            .iter()
+            .filter(|(_, values)| !values.is_empty())
+            .map(|(label, values)| format!("{}: {}", label, values))
+            .collect();
+        f.write_str(lines.join("\n").as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::db::{AmsInfo, GpdInfo};
+
+    use super::*;
+
+    #[test]
+    fn between_test() {
+        let mut old = TaxDb::default();
+        for income_total in [dec!(100), dec!(200), dec!(300)] {
+            old.add_ams_info(
+                AmsInfo {
+                    income_total,
+                    ..Default::default()
+                },
+                "2021-03-10".to_string(),
+            );
+        }
+        old.add_gpd_info(GpdInfo::default(), "2020".to_string());
+
+        let mut new = TaxDb {
+            ams: old.ams.clone(),
+            ..Default::default()
+        };
+        new.remove_ams(1);
+        new.get_ams_mut(2).unwrap().tax_paid = dec!(10);
+        new.add_ams_info(Default::default(), "2021-04-10".to_string());
+        new.add_gpd_info(GpdInfo::default(), "2021".to_string());
+
+        let diff = DbDiff::between(&old, &new);
+        assert_eq!(vec![4], diff.ams_added);
+        assert_eq!(vec![1], diff.ams_removed);
+        assert_eq!(vec![2], diff.ams_changed);
+        assert_eq!(vec!["2021".to_string()], diff.gpd_added);
+        assert_eq!(vec!["2020".to_string()], diff.gpd_removed);
+        assert!(diff.gpd_changed.is_empty());
+        assert!(DbDiff::between(&old, &old).is_empty());
+    }
+}
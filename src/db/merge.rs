@@ -0,0 +1,343 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::error::Result;
+
+use super::{migrations, AmsInfo, GpdInfo, TaxDb};
+
+/// Copy of the database a record is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    First,
+    Second,
+}
+
+/// Record present in both copies with different contents
+pub enum Conflict<'a> {
+    Ams(&'a AmsInfo, &'a AmsInfo),
+    /// Entry present only in the given copy, while the other copy has already given its id
+    /// to an entry, so it was most likely removed there. Picking the other copy removes it.
+    AmsRemoved(Side, &'a AmsInfo),
+    /// Key of GPD results, with results from both copies
+    Gpd(&'a str, &'a GpdInfo, &'a GpdInfo),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// Records present only in the second copy, added to the merged database
+    pub added: usize,
+    /// Records present in both copies with the same contents
+    pub identical: usize,
+    pub conflicts_first: usize,
+    pub conflicts_second: usize,
+}
+
+impl Display for MergeSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Added {} records from the second copy, {} identical records, {} conflicts resolved with the first copy and {} with the second",
+            self.added, self.identical, self.conflicts_first, self.conflicts_second
+        ))
+    }
+}
+
+fn same_value<T: Serialize>(first: &T, second: &T) -> bool {
+    serde_json::to_value(first).ok() == serde_json::to_value(second).ok()
+}
+
+/// Same entry contents, ignoring ids which both copies assign independently
+fn same_ams(first: &AmsInfo, second: &AmsInfo) -> bool {
+    same_value(
+        &AmsInfo {
+            id: 0,
+            ..first.clone()
+        },
+        &AmsInfo {
+            id: 0,
+            ..second.clone()
+        },
+    )
+}
+
+/// Whether the entries are the same record, possibly changed in one of the copies.
+/// Entries added independently in both copies under the same id are different records,
+/// so an entry whose date and income were both changed can't be told apart from them.
+fn same_record(first: &AmsInfo, second: &AmsInfo) -> bool {
+    first.is_duplicate_of(second)
+        || (first.id == second.id
+            && first.taxpayer == second.taxpayer
+            && first.income_total == second.income_total)
+}
+
+/// Whether the entry is missing from the other copy, which has already used its id
+fn removed_from(other: &TaxDb, entry: &AmsInfo) -> bool {
+    entry.id < other.next_ams_id
+        && other.get_ams(entry.id).is_none()
+        && !other
+            .ams
+            .iter()
+            .any(|v| same_ams(v, entry) || same_record(v, entry))
+}
+
+/// Combines two copies of the database into one. This is a two-way merge, without
+/// the common ancestor of the copies, so which copy changed a record is not known:
+/// `resolve` picks the copy to take the record from when copies differ. Entries found only
+/// in one of the copies are kept, unless the other copy has already used their id, which
+/// means they were most likely removed there. Those are passed to `resolve` as well.
+/// AMS entries are the same record when they are duplicates of each other or when they
+/// have the same id and income (for example, when only their date was changed),
+/// while other entries added in both copies under the same id are both kept.
+pub fn merge<F>(first: &TaxDb, second: &TaxDb, mut resolve: F) -> Result<(TaxDb, MergeSummary)>
+where
+    F: FnMut(&Conflict) -> Result<Side>,
+{
+    let mut summary = MergeSummary::default();
+    let mut merged = TaxDb {
+        schema_version: migrations::CURRENT_SCHEMA_VERSION,
+        next_ams_id: first.next_ams_id.max(second.next_ams_id),
+        ams: first.ams.clone(),
+        ..Default::default()
+    };
+
+    let mut first_ams: Vec<&AmsInfo> = first.ams.iter().collect();
+    first_ams.sort_by_key(|v| v.id);
+    for entry in first_ams {
+        if removed_from(second, entry) {
+            match resolve(&Conflict::AmsRemoved(Side::First, entry))? {
+                Side::First => summary.conflicts_first += 1,
+                Side::Second => {
+                    merged.remove_ams(entry.id);
+                    summary.conflicts_second += 1;
+                }
+            }
+        }
+    }
+
+    let mut second_ams: Vec<&AmsInfo> = second.ams.iter().collect();
+    second_ams.sort_by_key(|v| v.id);
+    let mut new_ams = Vec::new();
+    for entry in second_ams {
+        if first.ams.iter().any(|existing| same_ams(existing, entry)) {
+            summary.identical += 1;
+            continue;
+        }
+        if removed_from(first, entry) {
+            match resolve(&Conflict::AmsRemoved(Side::Second, entry))? {
+                Side::First => summary.conflicts_first += 1,
+                Side::Second => {
+                    merged.ams.push(entry.clone());
+                    summary.conflicts_second += 1;
+                }
+            }
+            continue;
+        }
+        match first
+            .ams
+            .iter()
+            .find(|existing| same_record(existing, entry))
+        {
+            Some(existing) => match resolve(&Conflict::Ams(existing, entry))? {
+                Side::First => summary.conflicts_first += 1,
+                Side::Second => {
+                    if let Some(merged_entry) = merged.get_ams_mut(existing.id) {
+                        *merged_entry = AmsInfo {
+                            id: existing.id,
+                            ..entry.clone()
+                        };
+                    }
+                    summary.conflicts_second += 1;
+                }
+            },
+            None => new_ams.push(entry),
+        }
+    }
+    // Ids of added entries are kept, unless the first copy already uses them
+    for entry in new_ams {
+        if merged.get_ams(entry.id).is_some() {
+            merged.add_ams_info(entry.clone(), entry.date.clone());
+        } else {
+            merged.ams.push(entry.clone());
+        }
+        summary.added += 1;
+    }
+
+    let mut keys: Vec<&String> = first.gpd.keys().chain(second.gpd.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let side = match (first.gpd.get(key), second.gpd.get(key)) {
+            (Some(_), None) => Side::First,
+            (None, Some(_)) => {
+                summary.added += 1;
+                Side::Second
+            }
+            (Some(first_info), Some(second_info)) if same_value(first_info, second_info) => {
+                summary.identical += 1;
+                Side::First
+            }
+            (Some(first_info), Some(second_info)) => {
+                let side = resolve(&Conflict::Gpd(key, first_info, second_info))?;
+                match side {
+                    Side::First => summary.conflicts_first += 1,
+                    Side::Second => summary.conflicts_second += 1,
+                }
+                side
+            }
+            (None, None) => continue,
+        };
+        let gpd_info = match side {
+            Side::First => &first.gpd[key],
+            Side::Second => &second.gpd[key],
+        };
+        merged.gpd.insert(key.clone(), gpd_info.clone());
+    }
+
+    merged.ams.sort_by_key(|v| v.id);
+    Ok((merged, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn ams_info(income_total: rust_decimal::Decimal) -> AmsInfo {
+        AmsInfo {
+            income_total,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut first = TaxDb::default();
+        first.add_ams_info(ams_info(dec!(100)), "2021-01-10".to_string());
+        first.add_ams_info(ams_info(dec!(200)), "2021-02-10".to_string());
+        first.add_gpd_info(GpdInfo::default(), "2020".to_string());
+
+        let mut second = TaxDb {
+            ams: first.ams.clone(),
+            ..Default::default()
+        };
+        second.get_ams_mut(2).unwrap().client = Some("Client".to_string());
+        // Added independently on both machines, under the same id
+        first.add_ams_info(ams_info(dec!(300)), "2021-03-10".to_string());
+        second.add_ams_info(ams_info(dec!(400)), "2021-04-10".to_string());
+        second.add_gpd_info(
+            GpdInfo {
+                loss: dec!(100),
+                ..Default::default()
+            },
+            "2020".to_string(),
+        );
+        second.add_gpd_info(GpdInfo::default(), "2021".to_string());
+
+        let mut conflicts = Vec::new();
+        let (merged, summary) = merge(&first, &second, |conflict| {
+            Ok(match conflict {
+                Conflict::Ams(existing, _) => {
+                    conflicts.push(format!("AMS {}", existing.id));
+                    Side::Second
+                }
+                Conflict::AmsRemoved(_, entry) => {
+                    conflicts.push(format!("Removed AMS {}", entry.id));
+                    Side::First
+                }
+                Conflict::Gpd(key, _, _) => {
+                    conflicts.push(format!("GPD {}", key));
+                    Side::First
+                }
+            })
+        })
+        .unwrap();
+
+        assert_eq!(vec!["AMS 2".to_string(), "GPD 2020".to_string()], conflicts);
+        assert_eq!(
+            MergeSummary {
+                added: 2,
+                identical: 1,
+                conflicts_first: 1,
+                conflicts_second: 1,
+            },
+            summary
+        );
+        let ids: Vec<(u32, &str)> = merged.ams.iter().map(|v| (v.id, v.date.as_str())).collect();
+        assert_eq!(
+            vec![
+                (1, "2021-01-10"),
+                (2, "2021-02-10"),
+                (3, "2021-03-10"),
+                (4, "2021-04-10")
+            ],
+            ids
+        );
+        assert_eq!(Some("Client".to_string()), merged.ams[1].client);
+        assert_eq!(dec!(0), merged.gpd["2020"].loss);
+        assert!(merged.gpd.contains_key("2021"));
+    }
+
+    #[test]
+    fn merge_removed_and_edited_test() {
+        let mut base = TaxDb::default();
+        for (income, date) in [
+            (dec!(100), "2021-01-10"),
+            (dec!(200), "2021-02-10"),
+            (dec!(300), "2021-03-10"),
+        ] {
+            base.add_ams_info(ams_info(income), date.to_string());
+        }
+        let mut first = TaxDb {
+            ams: base.ams.clone(),
+            next_ams_id: base.next_ams_id,
+            ..Default::default()
+        };
+        first.remove_ams(1);
+        first.get_ams_mut(2).unwrap().date = "2021-02-11".to_string();
+        let mut second = base;
+        second.remove_ams(3);
+        second.add_ams_info(ams_info(dec!(400)), "2021-04-10".to_string());
+
+        let mut conflicts = Vec::new();
+        let (merged, summary) = merge(&first, &second, |conflict| {
+            Ok(match conflict {
+                Conflict::Ams(existing, entry) => {
+                    conflicts.push(format!("AMS {} {}", existing.id, entry.date));
+                    Side::First
+                }
+                Conflict::AmsRemoved(side, entry) => {
+                    conflicts.push(format!("Removed AMS {}", entry.id));
+                    // Removal is kept, by picking the copy the entry is missing from
+                    match side {
+                        Side::First => Side::Second,
+                        Side::Second => Side::First,
+                    }
+                }
+                Conflict::Gpd(..) => Side::First,
+            })
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "Removed AMS 3".to_string(),
+                "Removed AMS 1".to_string(),
+                "AMS 2 2021-02-10".to_string()
+            ],
+            conflicts
+        );
+        assert_eq!(
+            MergeSummary {
+                added: 1,
+                identical: 0,
+                conflicts_first: 2,
+                conflicts_second: 1,
+            },
+            summary
+        );
+        let ids: Vec<(u32, &str)> = merged.ams.iter().map(|v| (v.id, v.date.as_str())).collect();
+        assert_eq!(vec![(2, "2021-02-11"), (4, "2021-04-10")], ids);
+        assert_eq!(5, merged.next_ams_id);
+    }
+}
@@ -0,0 +1,166 @@
+use serde_json::{json, Map, Value};
+
+use crate::{
+    db::calculate_due_date,
+    error::{Error, Result, UserErrorKind},
+};
+
+/// Version of the database schema written by this version of fbihtax
+pub static CURRENT_SCHEMA_VERSION: u32 = 4;
+
+/// Databases written before schema versioning was introduced have no version field
+static INITIAL_SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Migration at index i upgrades the database from version i + 1 to version i + 2
+static MIGRATIONS: [Migration; 3] = [migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+pub fn schema_version(db: &Value) -> Result<u32> {
+    match db.get("schemaVersion") {
+        None => Ok(INITIAL_SCHEMA_VERSION),
+        Some(version) => version
+            .as_u64()
+            .map(|v| v as u32)
+            .ok_or(Error::UnexpectedCondition(format!(
+                "Invalid database schema version: {}",
+                version
+            ))),
+    }
+}
+
+/// Upgrades database to the current schema version.
+/// Databases written by newer versions of fbihtax are refused.
+pub fn migrate(db: &mut Value) -> Result<()> {
+    let version = schema_version(db)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(Error::UserError(UserErrorKind::UnsupportedSchemaVersion(
+            version,
+            CURRENT_SCHEMA_VERSION,
+        )));
+    }
+    let db_object = db.as_object_mut().ok_or(Error::UnexpectedCondition(
+        "Database file does not contain a JSON object".to_string(),
+    ))?;
+    for migration in MIGRATIONS
+        .iter()
+        .skip((version - INITIAL_SCHEMA_VERSION) as usize)
+    {
+        migration(db_object)?;
+    }
+    db_object.insert("schemaVersion".to_string(), json!(CURRENT_SCHEMA_VERSION));
+    Ok(())
+}
+
+/// AMS entries used to be stored in a map keyed by invoice date,
+/// which lost entries with the same date. They are now a list of entries with unique ids.
+fn migrate_v1_to_v2(db: &mut Map<String, Value>) -> Result<()> {
+    if let Some(Value::Object(ams_map)) = db.get("ams") {
+        let mut legacy_entries: Vec<(&String, &Value)> = ams_map.iter().collect();
+        legacy_entries.sort_by_key(|(date, _)| *date);
+        let ams_list: Vec<Value> = legacy_entries
+            .into_iter()
+            .zip(1..)
+            .map(|((date, info), id)| {
+                let mut entry = info.as_object().cloned().unwrap_or_default();
+                entry.insert("id".to_string(), json!(id));
+                entry.insert("date".to_string(), json!(date));
+                Value::Object(entry)
+            })
+            .collect();
+        db.insert("ams".to_string(), Value::Array(ams_list));
+    }
+    Ok(())
+}
+
+/// Entries got due dates and payment status. Entries recorded before payment
+/// tracking are considered paid, since only paid tax used to be recorded.
+fn migrate_v2_to_v3(db: &mut Map<String, Value>) -> Result<()> {
+    if let Some(Value::Array(ams_list)) = db.get_mut("ams") {
+        for entry in ams_list
+            .iter_mut()
+            .filter_map(|entry| entry.as_object_mut())
+        {
+            let due_date = entry
+                .get("date")
+                .and_then(|date| date.as_str())
+                .and_then(calculate_due_date);
+            entry.insert("dueDate".to_string(), json!(due_date));
+            entry.insert("paid".to_string(), json!(true));
+        }
+    }
+    Ok(())
+}
+
+/// Ids of new entries used to follow the highest id, so ids of deleted entries were reused.
+/// The next id is now stored, starting after the highest id still present.
+fn migrate_v3_to_v4(db: &mut Map<String, Value>) -> Result<()> {
+    let highest_id = match db.get("ams") {
+        Some(Value::Array(ams_list)) => ams_list
+            .iter()
+            .filter_map(|entry| entry.get("id").and_then(|id| id.as_u64()))
+            .max()
+            .unwrap_or(0),
+        _ => 0,
+    };
+    db.insert("nextAmsId".to_string(), json!(highest_id + 1));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::db::TaxDb;
+
+    #[test]
+    fn legacy_ams_map_migration_test() {
+        let mut value: Value = serde_json::from_str(
+            r#"{
+                "ams": {
+                    "2021-05-01": { "incomeTotal": "200", "taxPaid": "20" },
+                    "2021-02-01": { "incomeTotal": "100", "taxPaid": "10" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(1, schema_version(&value).unwrap());
+
+        migrate(&mut value).unwrap();
+        assert_eq!(CURRENT_SCHEMA_VERSION, schema_version(&value).unwrap());
+
+        let db: TaxDb = serde_json::from_value(value).unwrap();
+        assert_eq!(2, db.ams.len());
+        assert_eq!(1, db.ams[0].id);
+        assert_eq!("2021-02-01", db.ams[0].date);
+        assert_eq!(dec!(100), db.ams[0].income_total);
+        assert_eq!(2, db.ams[1].id);
+        assert_eq!("2021-05-01", db.ams[1].date);
+        assert_eq!(dec!(20), db.ams[1].tax_paid);
+        assert_eq!(None, db.ams[1].income);
+        assert_eq!("BAM", db.ams[1].currency);
+        assert_eq!(Some("2021-03-10".to_string()), db.ams[0].due_date);
+        assert!(db.ams[0].paid);
+        assert_eq!(3, db.next_ams_id);
+    }
+
+    #[test]
+    fn current_version_is_not_migrated_test() {
+        let mut value = json!({
+            "schemaVersion": CURRENT_SCHEMA_VERSION,
+            "ams": [{ "id": 7, "date": "2021-02-01", "incomeTotal": "100", "taxPaid": "10" }]
+        });
+        let original = value.clone();
+
+        migrate(&mut value).unwrap();
+        assert_eq!(original, value);
+    }
+
+    #[test]
+    fn newer_version_is_refused_test() {
+        let mut value = json!({ "schemaVersion": CURRENT_SCHEMA_VERSION + 1 });
+
+        assert!(migrate(&mut value).is_err());
+    }
+}
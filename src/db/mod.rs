@@ -1,76 +1,1127 @@
-use std::{collections::HashMap, fs::File, io::BufReader};
+pub mod crypto;
+pub mod diff;
+pub mod merge;
+pub mod migrations;
+pub mod storage;
+pub mod verify;
 
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, NaiveDate};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
-use crate::error::{Error, Result};
+use crate::{
+    dates::{self, Period},
+    error::{Error, Result, UserErrorKind},
+    forms::formutils::format_money_value,
+    taxcalculator,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TaxDb {
-    #[serde(default = "default_ams_map")]
-    pub ams: HashMap<String, AmsInfo>,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    /// Id the next added AMS entry gets. Ids of deleted entries are never reused,
+    /// so history of an id in the journal always belongs to the same entry.
+    #[serde(default = "default_next_ams_id")]
+    pub next_ams_id: u32,
+    #[serde(default = "default_ams_list", serialize_with = "serialize_ams_sorted")]
+    pub ams: Vec<AmsInfo>,
+    #[serde(default = "default_gpd_map")]
+    pub gpd: BTreeMap<String, GpdInfo>,
 }
 
-fn default_ams_map() -> HashMap<String, AmsInfo> {
-    return HashMap::new();
+/// Entries are written sorted by id, so the database file stays stable under version control
+fn serialize_ams_sorted<S: Serializer>(
+    ams: &[AmsInfo],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&AmsInfo> = ams.iter().collect();
+    sorted.sort_by_key(|v| v.id);
+    serializer.collect_seq(sorted)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn default_schema_version() -> u32 {
+    migrations::CURRENT_SCHEMA_VERSION
+}
+
+fn default_next_ams_id() -> u32 {
+    1
+}
+
+fn default_ams_list() -> Vec<AmsInfo> {
+    Vec::new()
+}
+
+fn default_gpd_map() -> BTreeMap<String, GpdInfo> {
+    BTreeMap::new()
+}
+
+/// Number of years a loss can be carried forward
+static LOSS_CARRYFORWARD_YEARS: i32 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AmsInfo {
+    #[serde(default)]
+    pub id: u32,
+    #[serde(default)]
+    pub date: String,
+    /// Income before deduction
+    #[serde(default)]
+    pub income: Option<Decimal>,
+    #[serde(default)]
+    pub deduction_percentage: Option<Decimal>,
+    /// Income after deduction
+    pub income_total: Decimal,
+    #[serde(default)]
+    pub health_insurance: Option<Decimal>,
+    pub tax_paid: Decimal,
+    #[serde(default)]
+    pub client: Option<String>,
+    /// Taxpayer profile id or JMBG, for databases shared by multiple taxpayers
+    #[serde(default)]
+    pub taxpayer: Option<String>,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub form_path: Option<String>,
+    /// SHA-256 of the form file the entry was loaded from, used to recognize already loaded files
+    #[serde(default)]
+    pub source_fingerprint: Option<String>,
+    /// Date by which the tax has to be paid
+    #[serde(default)]
+    pub due_date: Option<String>,
+    #[serde(default)]
+    pub paid: bool,
+    #[serde(default)]
+    pub paid_date: Option<String>,
+    #[serde(default)]
+    pub payment_reference: Option<String>,
+}
+
+fn default_currency() -> String {
+    "BAM".to_string()
+}
+
+/// Columns of AMS entries, in the order they are presented
+pub static AMS_COLUMNS: [&str; 15] = [
+    "id",
+    "date",
+    "client",
+    "taxpayer",
+    "income",
+    "deductionPercentage",
+    "incomeTotal",
+    "healthInsurance",
+    "taxPaid",
+    "currency",
+    "formPath",
+    "dueDate",
+    "paid",
+    "paidDate",
+    "paymentReference",
+];
+
+#[derive(Debug, Default)]
+pub struct AmsFilter {
+    pub year: Option<i32>,
+    /// Quarter of the year (1-4)
+    pub quarter: Option<u32>,
+    pub month: Option<u32>,
+    pub client: Option<String>,
+    /// Exact taxpayer entries belong to
+    pub taxpayer: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl AmsFilter {
+    /// Period covered by the year, quarter and month filters, if the year is known
+    pub fn period(&self) -> Option<Period> {
+        let year = self.year?;
+        Some(match (self.month, self.quarter) {
+            (Some(month), _) => Period::Month(year, month),
+            (None, Some(quarter)) => Period::Quarter(year, quarter),
+            (None, None) => Period::Year(year),
+        })
+    }
+
+    /// Whether any of the date based filters is set
+    pub fn filters_dates(&self) -> bool {
+        self.year.is_some()
+            || self.quarter.is_some()
+            || self.month.is_some()
+            || self.from.is_some()
+            || self.to.is_some()
+    }
+
+    pub fn matches(&self, ams_info: &AmsInfo) -> bool {
+        if let Some(client) = &self.client {
+            let matches_client = ams_info
+                .client
+                .as_ref()
+                .map(|c| c.to_lowercase().contains(&client.to_lowercase()))
+                .unwrap_or(false);
+            if !matches_client {
+                return false;
+            }
+        }
+        if self.taxpayer.is_some() && self.taxpayer != ams_info.taxpayer {
+            return false;
+        }
+        if !self.filters_dates() {
+            return true;
+        }
+        let date = match dates::parse_date(&ams_info.date) {
+            Ok(date) => date,
+            Err(_) => return false,
+        };
+        self.year.map(|y| date.year() == y).unwrap_or(true)
+            && self
+                .quarter
+                .map(|q| date.month0() / 3 + 1 == q)
+                .unwrap_or(true)
+            && self.month.map(|m| date.month() == m).unwrap_or(true)
+            && self.from.map(|from| date >= from).unwrap_or(true)
+            && self.to.map(|to| date <= to).unwrap_or(true)
+    }
+}
+
+/// Changes of an AMS entry. Fields which are not set are left as they are.
+#[derive(Debug, Default)]
+pub struct AmsChanges {
+    pub income: Option<Decimal>,
+    pub deduced_income: Option<Decimal>,
+    pub deduction_percentage: Option<Decimal>,
+    pub invoice_date: Option<String>,
+    pub client: Option<String>,
+    pub currency: Option<String>,
+    pub taxpayer: Option<String>,
+}
+
+impl AmsInfo {
+    pub fn to_dict(&self) -> HashMap<String, String> {
+        self.to_dict_with_money_format(format_money_value)
+    }
+
+    /// Same as `to_dict`, but money values are kept at full precision
+    pub fn to_export_dict(&self) -> HashMap<String, String> {
+        self.to_dict_with_money_format(|value| value.to_string())
+    }
+
+    fn to_dict_with_money_format(
+        &self,
+        format_money: fn(Decimal) -> String,
+    ) -> HashMap<String, String> {
+        let mut result: HashMap<String, String> = HashMap::new();
+        result.insert("id".to_string(), self.id.to_string());
+        result.insert("date".to_string(), self.date.clone());
+        result.insert(
+            "client".to_string(),
+            self.client.clone().unwrap_or_default(),
+        );
+        result.insert(
+            "taxpayer".to_string(),
+            self.taxpayer.clone().unwrap_or_default(),
+        );
+        result.insert(
+            "income".to_string(),
+            self.income.map(format_money).unwrap_or_default(),
+        );
+        result.insert(
+            "deductionPercentage".to_string(),
+            self.deduction_percentage
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+        );
+        result.insert("incomeTotal".to_string(), format_money(self.income_total));
+        result.insert(
+            "healthInsurance".to_string(),
+            self.health_insurance.map(format_money).unwrap_or_default(),
+        );
+        result.insert("taxPaid".to_string(), format_money(self.tax_paid));
+        result.insert("currency".to_string(), self.currency.clone());
+        result.insert(
+            "formPath".to_string(),
+            self.form_path.clone().unwrap_or_default(),
+        );
+        result.insert(
+            "dueDate".to_string(),
+            self.due_date.clone().unwrap_or_default(),
+        );
+        result.insert("paid".to_string(), self.paid.to_string());
+        result.insert(
+            "paidDate".to_string(),
+            self.paid_date.clone().unwrap_or_default(),
+        );
+        result.insert(
+            "paymentReference".to_string(),
+            self.payment_reference.clone().unwrap_or_default(),
+        );
+        result
+    }
+
+    /// Parses entry from a dict in `to_dict` format. Missing derived values are calculated
+    pub fn from_dict(dict: &HashMap<String, String>) -> Result<AmsInfo> {
+        let value = |key: &str| -> Option<String> {
+            dict.get(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let decimal = |key: &str| -> Result<Option<Decimal>> {
+            value(key)
+                .map(|v| {
+                    Decimal::from_str_radix(&v, 10).map_err(|_| {
+                        Error::UserError(UserErrorKind::Generic(format!(
+                            "Invalid number in {}: {}",
+                            key, v
+                        )))
+                    })
+                })
+                .transpose()
+        };
+
+        let date = value("date").ok_or(Error::UserError(UserErrorKind::Generic(
+            "Missing date".to_string(),
+        )))?;
+        dates::parse_date(&date)?;
+        let id = match value("id") {
+            Some(id) => id.parse::<u32>().map_err(|_| {
+                Error::UserError(UserErrorKind::Generic(format!("Invalid id: {}", id)))
+            })?,
+            None => 0,
+        };
+        let income = decimal("income")?;
+        let deduction_percentage = decimal("deductionPercentage")?;
+        let income_total = match (decimal("incomeTotal")?, income) {
+            (Some(income_total), _) => income_total,
+            (None, Some(income)) => taxcalculator::income_after_deduction(
+                income,
+                deduction_percentage.unwrap_or(dec!(20)),
+            ),
+            (None, None) => {
+                return Err(Error::UserError(UserErrorKind::Generic(
+                    "Missing incomeTotal or income".to_string(),
+                )))
+            }
+        };
+        let optional_date = |key: &str| -> Result<Option<String>> {
+            value(key)
+                .map(|v| dates::parse_date(&v).map(|_| v))
+                .transpose()
+        };
+        let paid_date = optional_date("paidDate")?;
+        let paid = match value("paid").map(|v| v.to_lowercase()).as_deref() {
+            Some("true" | "yes" | "1") => true,
+            Some("false" | "no" | "0") => false,
+            Some(other) => {
+                return Err(Error::UserError(UserErrorKind::Generic(format!(
+                    "Invalid value in paid: {}",
+                    other
+                ))))
+            }
+            None => paid_date.is_some(),
+        };
+        Ok(AmsInfo {
+            id,
+            due_date: match optional_date("dueDate")? {
+                Some(due_date) => Some(due_date),
+                None => calculate_due_date(&date),
+            },
+            paid,
+            paid_date,
+            payment_reference: value("paymentReference"),
+            date,
+            income,
+            deduction_percentage,
+            income_total,
+            health_insurance: Some(
+                decimal("healthInsurance")?
+                    .unwrap_or_else(|| taxcalculator::health_insurance_part(income_total)),
+            ),
+            tax_paid: decimal("taxPaid")?
+                .unwrap_or_else(|| taxcalculator::tax_amount(income_total)),
+            client: value("client"),
+            taxpayer: value("taxpayer"),
+            currency: value("currency").unwrap_or_else(default_currency),
+            form_path: value("formPath"),
+            source_fingerprint: None,
+        })
+    }
+
+    /// Entry as stored in the database under the given id. Due date is calculated from
+    /// the invoice date, unless it is already set.
+    pub fn stored_as(self, id: u32, invoice_date: String) -> AmsInfo {
+        AmsInfo {
+            id,
+            due_date: self
+                .due_date
+                .clone()
+                .or_else(|| calculate_due_date(&invoice_date)),
+            date: invoice_date,
+            ..self
+        }
+    }
+
+    /// Entry with the changes applied. Health insurance and tax are recalculated when income
+    /// or deduction percentage change, and due date when invoice date changes.
+    pub fn with_changes(&self, changes: &AmsChanges) -> Result<AmsInfo> {
+        if changes.income.is_some() && changes.deduced_income.is_some() {
+            return Err(Error::UserError(UserErrorKind::Generic(
+                "Provide either --income or --deduced-income, not both!".to_string(),
+            )));
+        }
+        let mut entry = self.clone();
+        let income_changed = changes.deduced_income.is_some()
+            || changes.income.is_some()
+            || changes.deduction_percentage.is_some();
+        if let Some(deduced_income) = changes.deduced_income {
+            entry.income = None;
+            entry.deduction_percentage = None;
+            entry.income_total = deduced_income.round_dp(2);
+        } else if income_changed {
+            let income = changes.income.or(entry.income).ok_or(Error::UserError(
+                UserErrorKind::Generic(
+                    "Income before deduction is not known for this entry. Provide --income or --deduced-income!"
+                        .to_string(),
+                ),
+            ))?;
+            let deduction_percentage = changes
+                .deduction_percentage
+                .or(entry.deduction_percentage)
+                .unwrap_or(dec!(20));
+            entry.income = Some(income.round_dp(2));
+            entry.deduction_percentage = Some(deduction_percentage.round_dp(2));
+            entry.income_total = taxcalculator::income_after_deduction(
+                income.round_dp(2),
+                deduction_percentage.round_dp(2),
+            );
+        }
+        if income_changed {
+            entry.health_insurance = Some(taxcalculator::health_insurance_part(entry.income_total));
+            entry.tax_paid = taxcalculator::tax_amount(entry.income_total);
+        }
+        if let Some(invoice_date) = &changes.invoice_date {
+            dates::parse_date(invoice_date)?;
+            entry.date = invoice_date.clone();
+            entry.due_date = calculate_due_date(invoice_date);
+        }
+        if let Some(client) = &changes.client {
+            entry.client = Some(client.clone());
+        }
+        if let Some(currency) = &changes.currency {
+            entry.currency = currency.clone();
+        }
+        if changes.taxpayer.is_some() {
+            entry.taxpayer = changes.taxpayer.clone();
+        }
+        Ok(entry)
+    }
+
+    /// Whether the tax is still not paid after its due date
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        !self.paid
+            && self
+                .due_date
+                .as_deref()
+                .and_then(|due_date| dates::parse_date(due_date).ok())
+                .map(|due_date| due_date < today)
+                .unwrap_or(false)
+    }
+
+    /// Whether the other entry records the same payment: it has the same id and date,
+    /// or it was loaded from the same file
+    pub fn is_duplicate_of(&self, other: &AmsInfo) -> bool {
+        (self.id != 0
+            && self.id == other.id
+            && self.date == other.date
+            && self.taxpayer == other.taxpayer)
+            || (self.source_fingerprint.is_some()
+                && self.source_fingerprint == other.source_fingerprint)
+    }
+
+    /// Whether the other entry of the same taxpayer has the same date and income.
+    /// It might record the same payment, but separate payments of the same amount
+    /// on the same day are similar as well.
+    pub fn is_similar_to(&self, other: &AmsInfo) -> bool {
+        self.date == other.date
+            && self.taxpayer == other.taxpayer
+            && self.income_total.round_dp(2) == other.income_total.round_dp(2)
+    }
+}
+
+/// Id for a new AMS entry. Ids are never reused, even if the entry with the highest id was removed.
+pub fn new_ams_id(next_ams_id: u32, highest_id: u32) -> u32 {
+    next_ams_id.max(highest_id + 1)
+}
+
+/// Warning about entries left out of date based queries, if there are any
+pub fn malformed_dates_warning<'a>(
+    malformed: impl IntoIterator<Item = &'a AmsInfo>,
+) -> Option<String> {
+    let malformed: Vec<String> = malformed
+        .into_iter()
+        .map(|v| format!("{} ({})", v.id, v.date))
+        .collect();
+    if malformed.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Warning: {} AMS entries have malformed dates and are not included: {}. Fix them with `fbihtax db edit`",
+        malformed.len(),
+        malformed.join(", ")
+    ))
+}
+
+/// Key of GPD results in the database. Results of taxpayers are stored under year:taxpayer
+pub fn gpd_key(year: &str, taxpayer: Option<&str>) -> String {
+    match taxpayer {
+        Some(taxpayer) => format!("{}:{}", year, taxpayer),
+        None => year.to_string(),
+    }
+}
+
+/// Year part of GPD results key
+pub fn gpd_year(key: &str) -> &str {
+    key.split(':').next().unwrap_or(key)
+}
+
+/// Due date of tax for income received on the given date, if the date is valid
+pub fn calculate_due_date(date: &str) -> Option<String> {
+    dates::parse_date(date).ok().map(|date| {
+        taxcalculator::ams_payment_due_date(date)
+            .format(dates::DATE_FORMAT)
+            .to_string()
+    })
+}
+
+/// Totals of AMS entries, in the same format as `AmsInfo::to_dict`
+pub fn ams_totals_to_dict(entries: &[&AmsInfo]) -> HashMap<String, String> {
+    let mut income = dec!(0);
+    let mut income_total = dec!(0);
+    let mut health_insurance = dec!(0);
+    let mut tax_paid = dec!(0);
+    for entry in entries {
+        income += entry.income.unwrap_or_default();
+        income_total += entry.income_total;
+        health_insurance += entry.health_insurance.unwrap_or_default();
+        tax_paid += entry.tax_paid;
+    }
+    let mut result: HashMap<String, String> = HashMap::new();
+    result.insert("id".to_string(), "Total".to_string());
+    result.insert("income".to_string(), format_money_value(income));
+    result.insert("incomeTotal".to_string(), format_money_value(income_total));
+    result.insert(
+        "healthInsurance".to_string(),
+        format_money_value(health_insurance),
+    );
+    result.insert("taxPaid".to_string(), format_money_value(tax_paid));
+    result
+}
+
+impl Default for AmsInfo {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            date: String::new(),
+            income: None,
+            deduction_percentage: None,
+            income_total: dec!(0),
+            health_insurance: None,
+            tax_paid: dec!(0),
+            client: None,
+            taxpayer: None,
+            currency: default_currency(),
+            form_path: None,
+            source_fingerprint: None,
+            due_date: None,
+            paid: false,
+            paid_date: None,
+            payment_reference: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GpdInfo {
+    /// Taxpayer profile id or JMBG, for databases shared by multiple taxpayers
+    pub taxpayer: Option<String>,
+    pub period_start: Option<String>,
+    pub period_end: Option<String>,
+    pub gip: Option<GpdIncomeInfo>,
+    pub agro: Option<GpdIncomeInfo>,
+    pub rent: Option<GpdIncomeInfo>,
+    pub rent2: Option<GpdIncomeInfo>,
+    pub ams: Option<GpdIncomeInfo>,
+    pub personal_deduction: Decimal,
+    pub health_deduction: Decimal,
+    pub interest_deduction: Decimal,
+    /// Loss from previous years available for deduction in this year
+    pub previous_loss: Decimal,
+    /// Loss from previous years deducted in this year
+    pub previous_loss_used: Decimal,
+    /// Loss made in this year, available to be carried forward
+    pub loss: Decimal,
     pub income_total: Decimal,
+    pub tax_base: Decimal,
+    pub tax_amount: Decimal,
+    pub tax_paid: Decimal,
+    pub tax_due: Decimal,
+    pub refund: Decimal,
+    pub output_file: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GpdIncomeInfo {
+    pub income: Decimal,
+    pub expense: Decimal,
     pub tax_paid: Decimal,
 }
 
 impl Default for TaxDb {
     fn default() -> Self {
         Self {
-            ams: default_ams_map(),
+            schema_version: default_schema_version(),
+            next_ams_id: default_next_ams_id(),
+            ams: default_ams_list(),
+            gpd: default_gpd_map(),
         }
     }
 }
 
 impl TaxDb {
-    pub fn total_income_for_year(&self, year: String) -> Decimal {
-        let mut total = dec!(0);
-        for (k, v) in &self.ams {
-            if k.starts_with(&year) {
-                total += v.income_total;
+    /// Entries of the taxpayer dated within the period, sorted chronologically.
+    /// Without a taxpayer, only entries not belonging to any taxpayer are included.
+    /// Entries with malformed dates are never included, see `malformed_dates`.
+    pub fn ams_in_period(&self, period: &Period, taxpayer: Option<&str>) -> Vec<&AmsInfo> {
+        let mut entries: Vec<&AmsInfo> = self
+            .ams
+            .iter()
+            .filter(|v| v.taxpayer.as_deref() == taxpayer)
+            .filter(|v| match dates::parse_date(&v.date) {
+                Ok(date) => period.contains(date),
+                _ => false,
+            })
+            .collect();
+        entries.sort_by(|l, r| l.date.cmp(&r.date).then(l.id.cmp(&r.id)));
+        entries
+    }
+
+    pub fn total_income(&self, period: &Period, taxpayer: Option<&str>) -> Decimal {
+        self.ams_in_period(period, taxpayer)
+            .iter()
+            .map(|v| v.income_total)
+            .sum()
+    }
+
+    pub fn total_tax_paid(&self, period: &Period, taxpayer: Option<&str>) -> Decimal {
+        self.ams_in_period(period, taxpayer)
+            .iter()
+            .map(|v| v.tax_paid)
+            .sum()
+    }
+
+    /// Entries whose date can't be parsed, which are left out of all date based queries
+    pub fn malformed_dates(&self) -> Vec<&AmsInfo> {
+        self.ams
+            .iter()
+            .filter(|v| dates::parse_date(&v.date).is_err())
+            .collect()
+    }
+
+    /// Warning about entries left out of date based queries, if there are any
+    pub fn malformed_dates_warning(&self) -> Option<String> {
+        malformed_dates_warning(self.malformed_dates())
+    }
+
+    /// Entries matching the filter, sorted chronologically
+    pub fn find_ams(&self, filter: &AmsFilter) -> Vec<&AmsInfo> {
+        let mut entries: Vec<&AmsInfo> = self.ams.iter().filter(|v| filter.matches(v)).collect();
+        entries.sort_by(|l, r| l.date.cmp(&r.date).then(l.id.cmp(&r.id)));
+        entries
+    }
+
+    pub fn get_ams(&self, id: u32) -> Option<&AmsInfo> {
+        self.ams.iter().find(|v| v.id == id)
+    }
+
+    pub fn get_ams_mut(&mut self, id: u32) -> Option<&mut AmsInfo> {
+        self.ams.iter_mut().find(|v| v.id == id)
+    }
+
+    pub fn remove_ams(&mut self, id: u32) -> Option<AmsInfo> {
+        let index = self.ams.iter().position(|v| v.id == id)?;
+        Some(self.ams.remove(index))
+    }
+
+    /// Reserves a new id for an AMS entry
+    fn take_ams_id(&mut self) -> u32 {
+        let highest_id = self.ams.iter().map(|v| v.id).max().unwrap_or(0);
+        let id = new_ams_id(self.next_ams_id, highest_id);
+        self.next_ams_id = id + 1;
+        id
+    }
+
+    /// Appends the entry to the database and returns its newly assigned id
+    pub fn add_ams_info(&mut self, ams_info: AmsInfo, invoice_date: String) -> u32 {
+        let id = self.take_ams_id();
+        self.ams.push(ams_info.stored_as(id, invoice_date));
+        id
+    }
+
+    pub fn add_gpd_info(&mut self, gpd_info: GpdInfo, year: String) {
+        self.gpd
+            .insert(gpd_key(&year, gpd_info.taxpayer.as_deref()), gpd_info);
+    }
+
+    pub fn get_gpd(&self, year: &str, taxpayer: Option<&str>) -> Option<&GpdInfo> {
+        self.gpd.get(&gpd_key(year, taxpayer))
+    }
+
+    /// GPD results of the taxpayer with their years, sorted by year.
+    /// Without a taxpayer, only results not belonging to any taxpayer are included.
+    pub fn gpd_for_taxpayer(&self, taxpayer: Option<&str>) -> Vec<(&str, &GpdInfo)> {
+        let mut result: Vec<(&str, &GpdInfo)> = self
+            .gpd
+            .iter()
+            .filter(|(_, v)| v.taxpayer.as_deref() == taxpayer)
+            .map(|(k, v)| (gpd_year(k), v))
+            .collect();
+        result.sort_by_key(|(year, _)| *year);
+        result
+    }
+
+    /// Unused loss of the taxpayer from the years before the given one, which can still be
+    /// carried forward. Older losses are used up first.
+    pub fn carried_forward_loss(&self, year: i32, taxpayer: Option<&str>) -> Decimal {
+        let mut gpd_years: Vec<(i32, &GpdInfo)> = self
+            .gpd_for_taxpayer(taxpayer)
+            .into_iter()
+            .filter_map(|(k, v)| k.parse::<i32>().ok().map(|y| (y, v)))
+            .filter(|(y, _)| *y < year)
+            .collect();
+        gpd_years.sort_by_key(|(y, _)| *y);
+
+        let mut losses: Vec<(i32, Decimal)> = Vec::new();
+        for (gpd_year, gpd_info) in gpd_years {
+            losses.retain(|(loss_year, _)| gpd_year - loss_year <= LOSS_CARRYFORWARD_YEARS);
+            let mut to_use = gpd_info.previous_loss_used;
+            for (_, remaining) in losses.iter_mut() {
+                let used = to_use.min(*remaining);
+                *remaining -= used;
+                to_use -= used;
             }
+            losses.push((gpd_year, gpd_info.loss));
         }
-        return total;
-    }
 
-    pub fn total_tax_paid_for_year(&self, year: String) -> Decimal {
         let mut total = dec!(0);
-        for (k, v) in &self.ams {
-            if k.starts_with(&year) {
-                total += v.tax_paid;
+        for (loss_year, remaining) in losses {
+            if year - loss_year <= LOSS_CARRYFORWARD_YEARS {
+                total += remaining;
             }
         }
-        return total;
+        total
     }
+}
 
-    pub fn add_ams_info(&mut self, ams_info: AmsInfo, invoice_date: String) {
-        self.ams.insert(invoice_date, ams_info);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpd_info(loss: Decimal, previous_loss_used: Decimal) -> GpdInfo {
+        GpdInfo {
+            loss,
+            previous_loss_used,
+            ..Default::default()
+        }
     }
 
-    pub fn write_to_file(&self, file: &str) -> Result<()> {
-        let breakdown_writer = File::create(file)?;
-        serde_json::to_writer_pretty(breakdown_writer, &self).map_err(Error::from)
+    #[test]
+    fn add_ams_info_same_date_test() {
+        let mut db = TaxDb::default();
+        let ams_info = |income_total| AmsInfo {
+            income_total,
+            ..Default::default()
+        };
+        let first_id = db.add_ams_info(ams_info(dec!(100)), "2021-03-15".to_string());
+        let second_id = db.add_ams_info(ams_info(dec!(200)), "2021-03-15".to_string());
+
+        assert_ne!(first_id, second_id);
+        assert_eq!(2, db.ams.len());
+        assert_eq!(
+            dec!(300),
+            db.total_income(
+                &Period::Range(
+                    NaiveDate::from_ymd(2021, 1, 1),
+                    NaiveDate::from_ymd(2021, 12, 31)
+                ),
+                None
+            )
+        );
     }
-}
 
-fn parse_from_reader<T: DeserializeOwned>(reader: BufReader<File>) -> Result<T> {
-    serde_json::from_reader(reader).map_err(Error::from)
-}
+    #[test]
+    fn ams_ids_are_not_reused_test() {
+        let mut db = TaxDb::default();
+        db.add_ams_info(AmsInfo::default(), "2021-03-15".to_string());
+        let second_id = db.add_ams_info(AmsInfo::default(), "2021-03-16".to_string());
+        db.remove_ams(second_id);
 
-pub fn parse_db<T: for<'de> Deserialize<'de>>(db_location: &str) -> Result<T> {
-    parse_from_reader(File::open(db_location).map(BufReader::new)?)
-}
+        let third_id = db.add_ams_info(AmsInfo::default(), "2021-03-17".to_string());
+        assert_eq!(3, third_id);
+        assert_eq!(4, db.next_ams_id);
+    }
 
-pub fn parse_db_with_default<T: Default + for<'de> Deserialize<'de>>(db_location: &str) -> T {
-    parse_db(db_location).unwrap_or_default()
+    #[test]
+    fn find_ams_test() {
+        let mut db = TaxDb::default();
+        let ams_info = |client: &str| AmsInfo {
+            client: Some(client.to_string()),
+            ..Default::default()
+        };
+        db.add_ams_info(ams_info("Client A"), "2021-05-01".to_string());
+        db.add_ams_info(ams_info("Client B"), "2021-02-01".to_string());
+        db.add_ams_info(ams_info("Client A"), "2020-02-01".to_string());
+
+        let all: Vec<u32> = db
+            .find_ams(&AmsFilter::default())
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        assert_eq!(vec![3, 2, 1], all);
+
+        let year_filter = AmsFilter {
+            year: Some(2021),
+            ..Default::default()
+        };
+        let by_year: Vec<u32> = db.find_ams(&year_filter).iter().map(|v| v.id).collect();
+        assert_eq!(vec![2, 1], by_year);
+
+        let month_and_client_filter = AmsFilter {
+            month: Some(2),
+            client: Some("client a".to_string()),
+            ..Default::default()
+        };
+        let by_month_and_client: Vec<u32> = db
+            .find_ams(&month_and_client_filter)
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        assert_eq!(vec![3], by_month_and_client);
+
+        let range_filter = AmsFilter {
+            from: Some(NaiveDate::from_ymd(2021, 1, 1)),
+            to: Some(NaiveDate::from_ymd(2021, 3, 1)),
+            ..Default::default()
+        };
+        let by_range: Vec<u32> = db.find_ams(&range_filter).iter().map(|v| v.id).collect();
+        assert_eq!(vec![2], by_range);
+    }
+
+    #[test]
+    fn ams_in_period_test() {
+        let mut db = TaxDb::default();
+        let ams_info = |income_total| AmsInfo {
+            income_total,
+            ..Default::default()
+        };
+        db.add_ams_info(ams_info(dec!(100)), "2021-03-31".to_string());
+        db.add_ams_info(ams_info(dec!(200)), "2021-04-01".to_string());
+        db.add_ams_info(ams_info(dec!(400)), "2021-4-15".to_string());
+        db.add_ams_info(ams_info(dec!(800)), "21-04-20".to_string());
+
+        assert_eq!(dec!(300), db.total_income(&Period::Year(2021), None));
+        assert_eq!(dec!(100), db.total_income(&Period::Quarter(2021, 1), None));
+        assert_eq!(dec!(200), db.total_income(&Period::Month(2021, 4), None));
+        assert_eq!(dec!(0), db.total_income(&Period::Month(2021, 13), None));
+
+        let malformed: Vec<u32> = db.malformed_dates().iter().map(|v| v.id).collect();
+        assert_eq!(vec![3, 4], malformed);
+        assert!(db
+            .malformed_dates_warning()
+            .unwrap()
+            .contains("3 (2021-4-15)"));
+    }
+
+    #[test]
+    fn ams_due_date_test() {
+        let mut db = TaxDb::default();
+        let id = db.add_ams_info(Default::default(), "2021-12-15".to_string());
+        let entry = db.get_ams_mut(id).unwrap();
+        assert_eq!(Some("2022-01-10".to_string()), entry.due_date);
+
+        assert!(!entry.is_overdue(NaiveDate::from_ymd(2022, 1, 10)));
+        assert!(entry.is_overdue(NaiveDate::from_ymd(2022, 1, 11)));
+        entry.paid = true;
+        assert!(!entry.is_overdue(NaiveDate::from_ymd(2022, 1, 11)));
+    }
+
+    #[test]
+    fn ams_with_changes_test() {
+        let mut db = TaxDb::default();
+        let id = db.add_ams_info(
+            AmsInfo {
+                income: Some(dec!(1000)),
+                deduction_percentage: Some(dec!(20)),
+                income_total: dec!(800),
+                health_insurance: Some(dec!(32)),
+                tax_paid: dec!(76.8),
+                ..Default::default()
+            },
+            "2021-03-15".to_string(),
+        );
+        let entry = db.get_ams(id).unwrap();
+
+        // Income is deduced with the stored deduction percentage
+        let edited = entry
+            .with_changes(&AmsChanges {
+                income: Some(dec!(2000.004)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(Some(dec!(2000.00)), edited.income);
+        assert_eq!(dec!(1600), edited.income_total);
+        assert_eq!(Some(dec!(64)), edited.health_insurance);
+        assert_eq!(dec!(153.6), edited.tax_paid);
+
+        // Deduction percentage is applied to the stored income
+        let edited = entry
+            .with_changes(&AmsChanges {
+                deduction_percentage: Some(dec!(30)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(Some(dec!(30)), edited.deduction_percentage);
+        assert_eq!(dec!(700), edited.income_total);
+        assert_eq!(Some(dec!(28)), edited.health_insurance);
+        assert_eq!(dec!(67.2), edited.tax_paid);
+
+        // Deduced income replaces income and its deduction
+        let deduced = entry
+            .with_changes(&AmsChanges {
+                deduced_income: Some(dec!(500)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(None, deduced.income);
+        assert_eq!(None, deduced.deduction_percentage);
+        assert_eq!(dec!(500), deduced.income_total);
+        assert_eq!(Some(dec!(20)), deduced.health_insurance);
+        assert_eq!(dec!(48), deduced.tax_paid);
+        // Income before deduction is no longer known
+        assert!(deduced
+            .with_changes(&AmsChanges {
+                deduction_percentage: Some(dec!(30)),
+                ..Default::default()
+            })
+            .is_err());
+        assert!(entry
+            .with_changes(&AmsChanges {
+                income: Some(dec!(100)),
+                deduced_income: Some(dec!(80)),
+                ..Default::default()
+            })
+            .is_err());
+
+        let edited = entry
+            .with_changes(&AmsChanges {
+                invoice_date: Some("2021-12-01".to_string()),
+                client: Some("Client".to_string()),
+                currency: Some("EUR".to_string()),
+                taxpayer: Some("ana".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!("2021-12-01", edited.date);
+        assert_eq!(Some("2022-01-10".to_string()), edited.due_date);
+        assert_eq!(Some("Client".to_string()), edited.client);
+        assert_eq!("EUR", edited.currency);
+        assert_eq!(Some("ana".to_string()), edited.taxpayer);
+        // Amounts are kept when income doesn't change
+        assert_eq!(entry.to_dict()["taxPaid"], edited.to_dict()["taxPaid"]);
+        assert!(entry
+            .with_changes(&AmsChanges {
+                invoice_date: Some("2021-02-30".to_string()),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn ams_dict_roundtrip_test() {
+        let ams_info = AmsInfo {
+            id: 4,
+            date: "2021-03-02".to_string(),
+            income: Some(dec!(1000)),
+            deduction_percentage: Some(dec!(20)),
+            income_total: dec!(800),
+            health_insurance: Some(dec!(32)),
+            tax_paid: dec!(76.8),
+            client: Some("Client".to_string()),
+            taxpayer: Some("ana".to_string()),
+            currency: "BAM".to_string(),
+            form_path: None,
+            source_fingerprint: None,
+            due_date: Some("2021-04-10".to_string()),
+            paid: true,
+            paid_date: Some("2021-04-05".to_string()),
+            payment_reference: Some("REF-1".to_string()),
+        };
+        let parsed = AmsInfo::from_dict(&ams_info.to_export_dict()).unwrap();
+
+        assert_eq!(ams_info.to_export_dict(), parsed.to_export_dict());
+    }
+
+    #[test]
+    fn ams_from_dict_calculates_missing_test() {
+        let mut dict = HashMap::new();
+        dict.insert("date".to_string(), "2021-03-02".to_string());
+        dict.insert("income".to_string(), "1000".to_string());
+        let parsed = AmsInfo::from_dict(&dict).unwrap();
+
+        assert_eq!(dec!(800), parsed.income_total);
+        assert_eq!(Some(dec!(32)), parsed.health_insurance);
+        assert_eq!(dec!(76.8), parsed.tax_paid);
+
+        dict.insert("date".to_string(), "02.03.2021".to_string());
+        assert!(AmsInfo::from_dict(&dict).is_err());
+        dict.insert("date".to_string(), "2021-03-02".to_string());
+        dict.insert("income".to_string(), "abc".to_string());
+        assert!(AmsInfo::from_dict(&dict).is_err());
+    }
+
+    #[test]
+    fn ams_duplicates_test() {
+        let entry = AmsInfo {
+            id: 1,
+            date: "2021-03-10".to_string(),
+            income_total: dec!(800),
+            ..Default::default()
+        };
+        let same_payment = AmsInfo {
+            id: 2,
+            ..entry.clone()
+        };
+        assert!(!same_payment.is_duplicate_of(&entry));
+        assert!(same_payment.is_similar_to(&entry));
+
+        let same_id = AmsInfo {
+            income_total: dec!(1000),
+            ..entry.clone()
+        };
+        assert!(same_id.is_duplicate_of(&entry));
+        assert!(!same_id.is_similar_to(&entry));
+
+        let fingerprint = Some("abc".to_string());
+        let same_file = AmsInfo {
+            id: 2,
+            source_fingerprint: fingerprint.clone(),
+            ..Default::default()
+        };
+        let loaded = AmsInfo {
+            source_fingerprint: fingerprint,
+            ..entry.clone()
+        };
+        assert!(same_file.is_duplicate_of(&loaded));
+        assert!(!same_file.is_duplicate_of(&entry));
+    }
+
+    #[test]
+    fn carried_forward_loss_test() {
+        let mut db = TaxDb::default();
+        db.add_gpd_info(gpd_info(dec!(1000), dec!(0)), "2018".to_string());
+        db.add_gpd_info(gpd_info(dec!(0), dec!(400)), "2019".to_string());
+        db.add_gpd_info(gpd_info(dec!(500), dec!(0)), "2020".to_string());
+
+        assert_eq!(dec!(0), db.carried_forward_loss(2018, None));
+        assert_eq!(dec!(1000), db.carried_forward_loss(2019, None));
+        assert_eq!(dec!(600), db.carried_forward_loss(2020, None));
+        assert_eq!(dec!(1100), db.carried_forward_loss(2021, None));
+        // 2018 loss expires after 5 years
+        assert_eq!(dec!(1100), db.carried_forward_loss(2023, None));
+        assert_eq!(dec!(500), db.carried_forward_loss(2024, None));
+        assert_eq!(dec!(0), db.carried_forward_loss(2026, None));
+    }
+
+    #[test]
+    fn carried_forward_loss_uses_oldest_first_test() {
+        let mut db = TaxDb::default();
+        db.add_gpd_info(gpd_info(dec!(300), dec!(0)), "2017".to_string());
+        db.add_gpd_info(gpd_info(dec!(700), dec!(0)), "2018".to_string());
+        db.add_gpd_info(gpd_info(dec!(0), dec!(500)), "2019".to_string());
+
+        assert_eq!(dec!(500), db.carried_forward_loss(2020, None));
+        // 2017 loss was fully used, so its expiry changes nothing
+        assert_eq!(dec!(500), db.carried_forward_loss(2023, None));
+        assert_eq!(dec!(0), db.carried_forward_loss(2024, None));
+    }
+
+    #[test]
+    fn taxpayers_test() {
+        let mut db = TaxDb::default();
+        let ams_info = |income_total, taxpayer: Option<&str>| AmsInfo {
+            income_total,
+            taxpayer: taxpayer.map(|t| t.to_string()),
+            ..Default::default()
+        };
+        db.add_ams_info(ams_info(dec!(100), None), "2021-03-10".to_string());
+        db.add_ams_info(ams_info(dec!(200), Some("ana")), "2021-03-10".to_string());
+        db.add_ams_info(ams_info(dec!(400), Some("ivan")), "2021-04-10".to_string());
+
+        let year = Period::Year(2021);
+        assert_eq!(dec!(100), db.total_income(&year, None));
+        assert_eq!(dec!(200), db.total_income(&year, Some("ana")));
+        assert_eq!(dec!(400), db.total_income(&year, Some("ivan")));
+
+        db.add_gpd_info(gpd_info(dec!(1000), dec!(0)), "2020".to_string());
+        db.add_gpd_info(
+            GpdInfo {
+                taxpayer: Some("ana".to_string()),
+                ..gpd_info(dec!(300), dec!(0))
+            },
+            "2020".to_string(),
+        );
+        assert_eq!(2, db.gpd.len());
+        assert_eq!(dec!(300), db.get_gpd("2020", Some("ana")).unwrap().loss);
+        assert_eq!(dec!(1000), db.carried_forward_loss(2021, None));
+        assert_eq!(dec!(300), db.carried_forward_loss(2021, Some("ana")));
+        assert_eq!(dec!(0), db.carried_forward_loss(2021, Some("ivan")));
+        let years: Vec<&str> = db
+            .gpd_for_taxpayer(Some("ana"))
+            .iter()
+            .map(|(year, _)| *year)
+            .collect();
+        assert_eq!(vec!["2020"], years);
+    }
+
+    #[test]
+    fn serialization_is_sorted_test() {
+        let mut db = TaxDb::default();
+        db.add_ams_info(AmsInfo::default(), "2021-03-10".to_string());
+        db.add_ams_info(AmsInfo::default(), "2021-01-10".to_string());
+        db.ams.reverse();
+        db.add_gpd_info(GpdInfo::default(), "2021".to_string());
+        db.add_gpd_info(GpdInfo::default(), "2020".to_string());
+
+        let value = serde_json::to_value(&db).unwrap();
+        let ids: Vec<u64> = value["ams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(vec![1, 2], ids);
+        let years: Vec<&String> = value["gpd"].as_object().unwrap().keys().collect();
+        assert_eq!(vec!["2020", "2021"], years);
+        assert_eq!(
+            serde_json::to_string(&db).unwrap(),
+            serde_json::to_string(&serde_json::from_value::<TaxDb>(value).unwrap()).unwrap()
+        );
+    }
 }
@@ -0,0 +1,264 @@
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, Local, NaiveDateTime, SubsecRound};
+
+use crate::{
+    db::{AmsFilter, AmsInfo, GpdInfo, TaxDb},
+    error::{Error, Result, UserErrorKind},
+};
+
+use super::{DbBackend, DbLock, DbStorage};
+
+static BACKUP_EXTENSION: &str = "bak";
+static BACKUP_NAME_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.3f";
+
+pub struct Backup {
+    /// Time of the backup, which also identifies it
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Backups are kept in a directory next to the database
+pub fn backups_dir(db_location: &str) -> PathBuf {
+    PathBuf::from(format!("{}.backups", db_location))
+}
+
+/// Lists backups of the database, oldest first
+pub fn list_backups(db_location: &str) -> Result<Vec<Backup>> {
+    let dir = backups_dir(db_location);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(BACKUP_EXTENSION) {
+            continue;
+        }
+        let name = path.file_stem().and_then(|name| name.to_str());
+        // Other files, like backups made before schema upgrades, are skipped
+        if let Some(name) =
+            name.filter(|name| NaiveDateTime::parse_from_str(name, BACKUP_NAME_FORMAT).is_ok())
+        {
+            backups.push(Backup {
+                name: name.to_string(),
+                path: path.clone(),
+            });
+        }
+    }
+    backups.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(backups)
+}
+
+/// Finds backup by its name or path
+pub fn find_backup(db_location: &str, backup: &str) -> Result<Backup> {
+    if let Some(found) = list_backups(db_location)?
+        .into_iter()
+        .find(|b| b.name == backup)
+    {
+        return Ok(found);
+    }
+    let path = Path::new(backup);
+    if path.is_file() {
+        return Ok(Backup {
+            name: backup.to_string(),
+            path: path.to_path_buf(),
+        });
+    }
+    Err(Error::UserError(UserErrorKind::Generic(format!(
+        "Backup not found: {}. Use `fbihtax db backups` to list available backups",
+        backup
+    ))))
+}
+
+/// Name of a new backup made at the given time. Names always sort after existing backups,
+/// even if those were made within the same millisecond.
+fn backup_name(now: NaiveDateTime, backups: &[Backup]) -> String {
+    let now = now.trunc_subsecs(3);
+    let latest = backups
+        .last()
+        .and_then(|backup| NaiveDateTime::parse_from_str(&backup.name, BACKUP_NAME_FORMAT).ok());
+    let time = match latest {
+        Some(latest) if latest >= now => latest + Duration::milliseconds(1),
+        _ => now,
+    };
+    time.format(BACKUP_NAME_FORMAT).to_string()
+}
+
+fn create_backup(db_location: &str, retention: usize, now: NaiveDateTime) -> Result<()> {
+    let dir = backups_dir(db_location);
+    fs::create_dir_all(&dir)?;
+    let name = backup_name(now, &list_backups(db_location)?);
+    fs::copy(
+        db_location,
+        dir.join(format!("{}.{}", name, BACKUP_EXTENSION)),
+    )?;
+
+    let backups = list_backups(db_location)?;
+    if backups.len() > retention {
+        for backup in &backups[..backups.len() - retention] {
+            fs::remove_file(&backup.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Storage which backs up the database before it is first written, keeping `retention`
+/// latest backups. Each command makes a single backup, no matter how many records it changes.
+pub struct BackedUpStorage {
+    inner: Box<dyn DbStorage>,
+    retention: usize,
+    backed_up: Cell<bool>,
+}
+
+impl BackedUpStorage {
+    pub fn new(inner: Box<dyn DbStorage>, retention: usize) -> BackedUpStorage {
+        BackedUpStorage {
+            inner,
+            retention,
+            backed_up: Cell::new(false),
+        }
+    }
+
+    fn backup(&self) -> Result<()> {
+        if self.retention > 0 && !self.backed_up.get() && self.exists() {
+            create_backup(self.location(), self.retention, Local::now().naive_local())?;
+        }
+        self.backed_up.set(true);
+        Ok(())
+    }
+}
+
+impl DbStorage for BackedUpStorage {
+    fn location(&self) -> &str {
+        self.inner.location()
+    }
+
+    fn backend(&self) -> DbBackend {
+        self.inner.backend()
+    }
+
+    fn schema_version(&self) -> Result<u32> {
+        self.inner.schema_version()
+    }
+
+    fn encryption_salt(&self) -> Result<Option<String>> {
+        self.inner.encryption_salt()
+    }
+
+    fn load(&self) -> Result<TaxDb> {
+        self.inner.load()
+    }
+
+    fn save(&self, tax_db: &TaxDb) -> Result<()> {
+        self.backup()?;
+        self.inner.save(tax_db)
+    }
+
+    fn lock(&self) -> Result<DbLock> {
+        self.inner.lock()
+    }
+
+    fn find_ams(&self, filter: &AmsFilter) -> Result<Vec<AmsInfo>> {
+        self.inner.find_ams(filter)
+    }
+
+    fn get_ams(&self, id: u32) -> Result<Option<AmsInfo>> {
+        self.inner.get_ams(id)
+    }
+
+    fn malformed_dates(&self) -> Result<Vec<AmsInfo>> {
+        self.inner.malformed_dates()
+    }
+
+    fn insert_ams(&self, ams_info: AmsInfo, invoice_date: String) -> Result<u32> {
+        self.backup()?;
+        self.inner.insert_ams(ams_info, invoice_date)
+    }
+
+    fn update_ams(&self, ams_info: &AmsInfo) -> Result<()> {
+        self.backup()?;
+        self.inner.update_ams(ams_info)
+    }
+
+    fn delete_ams(&self, id: u32) -> Result<()> {
+        self.backup()?;
+        self.inner.delete_ams(id)
+    }
+
+    fn save_gpd(&self, gpd_info: GpdInfo, year: String) -> Result<()> {
+        self.backup()?;
+        self.inner.save_gpd(gpd_info, year)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::db::storage::JsonStorage;
+
+    #[test]
+    fn backups_retention_test() {
+        let dir = std::env::temp_dir().join(format!("fbihtax-backups-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let location = dir.join("db.json");
+        let location = location.to_str().unwrap();
+        // Each command opens its own storage
+        let storage = || BackedUpStorage::new(Box::new(JsonStorage::new(location)), 2);
+
+        let mut tax_db = TaxDb::default();
+        storage().save(&tax_db).unwrap();
+        assert!(list_backups(location).unwrap().is_empty());
+        for date in ["2021-01-10", "2021-02-10", "2021-03-10"] {
+            tax_db.add_ams_info(Default::default(), date.to_string());
+            storage().save(&tax_db).unwrap();
+        }
+        // Only the first write of a command is backed up
+        let storage = storage();
+        storage
+            .insert_ams(Default::default(), "2021-04-10".to_string())
+            .unwrap();
+        storage
+            .insert_ams(Default::default(), "2021-05-10".to_string())
+            .unwrap();
+
+        let backups = list_backups(location).unwrap();
+        assert_eq!(2, backups.len());
+        let latest = JsonStorage::new(backups[1].path.to_str().unwrap())
+            .load()
+            .unwrap();
+        assert_eq!(3, latest.ams.len());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backup_name_test() {
+        let time = |millis| NaiveDate::from_ymd(2021, 3, 2).and_hms_milli(10, 15, 0, millis);
+        let backup = |name: &str| Backup {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+        };
+
+        assert_eq!("2021-03-02T10-15-00.123", backup_name(time(123), &[]));
+        assert_eq!(
+            "2021-03-02T10-15-00.123",
+            backup_name(time(123), &[backup("2021-03-02T10-15-00.122")])
+        );
+        // Backups made within the same millisecond, or after the clock went back
+        assert_eq!(
+            "2021-03-02T10-15-00.124",
+            backup_name(time(123), &[backup("2021-03-02T10-15-00.123")])
+        );
+        assert_eq!(
+            "2021-03-02T10-15-01.000",
+            backup_name(time(123), &[backup("2021-03-02T10-15-00.999")])
+        );
+    }
+}
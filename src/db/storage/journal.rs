@@ -0,0 +1,418 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use chrono::{Datelike, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    dates,
+    db::{self, crypto, diff::DbDiff, AmsFilter, AmsInfo, GpdInfo, TaxDb},
+    error::{Error, Result},
+};
+
+use super::{DbBackend, DbLock, DbStorage};
+
+static TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Ams,
+    Gpd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Removed,
+}
+
+/// Single change of a record, as stored in the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub timestamp: String,
+    /// Command line of the command which made the change
+    pub command: String,
+    pub record: RecordKind,
+    /// Id of AMS entry or year of GPD result
+    pub key: String,
+    pub change: ChangeKind,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl JournalEntry {
+    /// Year the changed record belongs to
+    pub fn year(&self) -> Option<String> {
+        match self.record {
+            RecordKind::Gpd => Some(db::gpd_year(&self.key).to_string()),
+            RecordKind::Ams => self
+                .after
+                .as_ref()
+                .or(self.before.as_ref())
+                .and_then(|value| value.get("date"))
+                .and_then(|date| date.as_str())
+                .and_then(|date| dates::parse_date(date).ok())
+                .map(|date| date.year().to_string()),
+        }
+    }
+
+    /// Fields which differ between before and after values, with their old and new values
+    pub fn changed_fields(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        let empty = serde_json::Map::new();
+        let before = self
+            .before
+            .as_ref()
+            .and_then(|v| v.as_object())
+            .unwrap_or(&empty);
+        let after = self
+            .after
+            .as_ref()
+            .and_then(|v| v.as_object())
+            .unwrap_or(&empty);
+        let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+        fields.sort();
+        fields.dedup();
+        // Missing fields and fields set to null are treated the same
+        let get = |values: &serde_json::Map<String, Value>, field: &String| {
+            values.get(field).filter(|value| !value.is_null()).cloned()
+        };
+        fields
+            .into_iter()
+            .map(|field| (field.clone(), get(before, field), get(after, field)))
+            .filter(|(_, before, after)| before != after)
+            .collect()
+    }
+}
+
+/// Journal is kept in a JSON lines file next to the database
+pub fn journal_location(db_location: &str) -> String {
+    format!("{}.journal", db_location)
+}
+
+/// Journal entries are encrypted with the same key as the database
+fn journal_line(entry: &JournalEntry, salt: Option<&str>) -> Result<String> {
+    let line = serde_json::to_string(entry)?;
+    match salt {
+        Some(salt) => Ok(serde_json::to_string(&crypto::encrypt(
+            line.as_bytes(),
+            salt,
+        )?)?),
+        None => Ok(line),
+    }
+}
+
+fn write_journal_lines(
+    db_location: &str,
+    entries: &[JournalEntry],
+    salt: Option<&str>,
+    append: bool,
+) -> Result<()> {
+    let mut journal = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(journal_location(db_location))?;
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(journal_line(entry, salt)?.as_str());
+        lines.push('\n');
+    }
+    journal.write_all(lines.as_bytes())?;
+    journal.sync_all().map_err(Error::from)
+}
+
+/// Rewrites the journal encrypted with the given salt, or decrypted if there is no salt
+pub fn set_journal_encryption(db_location: &str, salt: Option<&str>) -> Result<()> {
+    if !Path::new(journal_location(db_location).as_str()).exists() {
+        return Ok(());
+    }
+    let entries = read_journal(db_location)?;
+    write_journal_lines(db_location, &entries, salt, false)
+}
+
+pub fn read_journal(db_location: &str) -> Result<Vec<JournalEntry>> {
+    let location = journal_location(db_location);
+    if !Path::new(location.as_str()).exists() {
+        return Ok(Vec::new());
+    }
+    let reader = BufReader::new(File::open(location.as_str())?);
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid_entry = |err: serde_json::Error| {
+            Error::UnexpectedCondition(format!(
+                "Invalid journal entry at line {} of {}: {}",
+                index + 1,
+                location,
+                err
+            ))
+        };
+        let value = crypto::decrypt_value(serde_json::from_str(&line).map_err(invalid_entry)?)?;
+        entries.push(serde_json::from_value(value).map_err(invalid_entry)?);
+    }
+    Ok(entries)
+}
+
+fn journal_entry(
+    command: &str,
+    record: RecordKind,
+    key: String,
+    change: ChangeKind,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> JournalEntry {
+    JournalEntry {
+        timestamp: Local::now().format(TIMESTAMP_FORMAT).to_string(),
+        command: command.to_string(),
+        record,
+        key,
+        change,
+        before,
+        after,
+    }
+}
+
+fn journal_entries(old: &TaxDb, new: &TaxDb, command: &str) -> Result<Vec<JournalEntry>> {
+    let diff = DbDiff::between(old, new);
+    let entry = |record, key, change, before, after| {
+        journal_entry(command, record, key, change, before, after)
+    };
+
+    let ams_value = |db: &TaxDb, id: u32| -> Result<Option<Value>> {
+        db.get_ams(id)
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(Error::from)
+    };
+    let gpd_value = |db: &TaxDb, year: &String| -> Result<Option<Value>> {
+        db.gpd
+            .get(year)
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(Error::from)
+    };
+
+    let mut entries = Vec::new();
+    for (ids, change) in [
+        (&diff.ams_added, ChangeKind::Added),
+        (&diff.ams_changed, ChangeKind::Changed),
+        (&diff.ams_removed, ChangeKind::Removed),
+    ] {
+        for id in ids {
+            entries.push(entry(
+                RecordKind::Ams,
+                id.to_string(),
+                change,
+                ams_value(old, *id)?,
+                ams_value(new, *id)?,
+            ));
+        }
+    }
+    for (years, change) in [
+        (&diff.gpd_added, ChangeKind::Added),
+        (&diff.gpd_changed, ChangeKind::Changed),
+        (&diff.gpd_removed, ChangeKind::Removed),
+    ] {
+        for year in years {
+            entries.push(entry(
+                RecordKind::Gpd,
+                year.clone(),
+                change,
+                gpd_value(old, year)?,
+                gpd_value(new, year)?,
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+/// Storage which appends each change of the database to the journal
+pub struct JournaledStorage {
+    pub inner: Box<dyn DbStorage>,
+    pub command: String,
+}
+
+impl JournaledStorage {
+    fn write_entries(&self, entries: &[JournalEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let salt = self.inner.encryption_salt()?;
+        write_journal_lines(self.location(), entries, salt.as_deref(), true)
+    }
+
+    fn write_ams_entry(
+        &self,
+        id: u32,
+        change: ChangeKind,
+        before: Option<&AmsInfo>,
+        after: Option<&AmsInfo>,
+    ) -> Result<()> {
+        let before = before.map(serde_json::to_value).transpose()?;
+        let after = after.map(serde_json::to_value).transpose()?;
+        if before == after {
+            return Ok(());
+        }
+        self.write_entries(&[journal_entry(
+            self.command.as_str(),
+            RecordKind::Ams,
+            id.to_string(),
+            change,
+            before,
+            after,
+        )])
+    }
+}
+
+impl DbStorage for JournaledStorage {
+    fn location(&self) -> &str {
+        self.inner.location()
+    }
+
+    fn backend(&self) -> DbBackend {
+        self.inner.backend()
+    }
+
+    fn schema_version(&self) -> Result<u32> {
+        self.inner.schema_version()
+    }
+
+    fn encryption_salt(&self) -> Result<Option<String>> {
+        self.inner.encryption_salt()
+    }
+
+    fn load(&self) -> Result<TaxDb> {
+        self.inner.load()
+    }
+
+    fn save(&self, tax_db: &TaxDb) -> Result<()> {
+        let previous = self.inner.load()?;
+        let entries = journal_entries(&previous, tax_db, self.command.as_str())?;
+        self.inner.save(tax_db)?;
+        self.write_entries(&entries)
+    }
+
+    fn lock(&self) -> Result<DbLock> {
+        self.inner.lock()
+    }
+
+    fn find_ams(&self, filter: &AmsFilter) -> Result<Vec<AmsInfo>> {
+        self.inner.find_ams(filter)
+    }
+
+    fn get_ams(&self, id: u32) -> Result<Option<AmsInfo>> {
+        self.inner.get_ams(id)
+    }
+
+    fn malformed_dates(&self) -> Result<Vec<AmsInfo>> {
+        self.inner.malformed_dates()
+    }
+
+    fn insert_ams(&self, ams_info: AmsInfo, invoice_date: String) -> Result<u32> {
+        let id = self.inner.insert_ams(ams_info, invoice_date)?;
+        let after = self.inner.get_ams(id)?;
+        self.write_ams_entry(id, ChangeKind::Added, None, after.as_ref())?;
+        Ok(id)
+    }
+
+    fn update_ams(&self, ams_info: &AmsInfo) -> Result<()> {
+        let before = self.inner.get_ams(ams_info.id)?;
+        self.inner.update_ams(ams_info)?;
+        self.write_ams_entry(
+            ams_info.id,
+            ChangeKind::Changed,
+            before.as_ref(),
+            Some(ams_info),
+        )
+    }
+
+    fn delete_ams(&self, id: u32) -> Result<()> {
+        let before = self.inner.get_ams(id)?;
+        self.inner.delete_ams(id)?;
+        self.write_ams_entry(id, ChangeKind::Removed, before.as_ref(), None)
+    }
+
+    fn save_gpd(&self, gpd_info: GpdInfo, year: String) -> Result<()> {
+        let key = db::gpd_key(&year, gpd_info.taxpayer.as_deref());
+        let before = self
+            .inner
+            .load()?
+            .gpd
+            .get(&key)
+            .map(serde_json::to_value)
+            .transpose()?;
+        let after = Some(serde_json::to_value(&gpd_info)?);
+        self.inner.save_gpd(gpd_info, year)?;
+        if before == after {
+            return Ok(());
+        }
+        let change = match before {
+            Some(_) => ChangeKind::Changed,
+            None => ChangeKind::Added,
+        };
+        self.write_entries(&[journal_entry(
+            self.command.as_str(),
+            RecordKind::Gpd,
+            key,
+            change,
+            before,
+            after,
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::db::storage::JsonStorage;
+
+    #[test]
+    fn journaled_save_test() {
+        let dir = std::env::temp_dir().join(format!("fbihtax-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let location = dir.join("db.json");
+        let location = location.to_str().unwrap();
+        let storage = JournaledStorage {
+            inner: Box::new(JsonStorage::new(location)),
+            command: "db insert".to_string(),
+        };
+
+        let mut tax_db = TaxDb::default();
+        tax_db.add_ams_info(
+            AmsInfo {
+                income_total: dec!(800),
+                ..Default::default()
+            },
+            "2021-03-10".to_string(),
+        );
+        storage.save(&tax_db).unwrap();
+        tax_db.get_ams_mut(1).unwrap().income_total = dec!(1200);
+        storage.save(&tax_db).unwrap();
+        storage.save(&tax_db).unwrap();
+
+        let entries = read_journal(location).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(ChangeKind::Added, entries[0].change);
+        assert_eq!(ChangeKind::Changed, entries[1].change);
+        assert_eq!(Some("2021".to_string()), entries[1].year());
+        let changed = entries[1].changed_fields();
+        assert_eq!(1, changed.len());
+        assert_eq!("incomeTotal", changed[0].0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+};
+
+use serde_json::Value;
+
+use crate::{
+    db::{crypto, migrations, TaxDb},
+    error::{Error, Result},
+};
+
+use super::{DbBackend, DbStorage};
+
+/// Stores the whole database in a single JSON file, optionally encrypted
+pub struct JsonStorage {
+    location: String,
+}
+
+impl JsonStorage {
+    pub fn new(location: &str) -> JsonStorage {
+        JsonStorage {
+            location: location.to_string(),
+        }
+    }
+
+    fn read_raw_value(&self) -> Result<Value> {
+        serde_json::from_reader(File::open(self.location.as_str()).map(BufReader::new)?)
+            .map_err(Error::from)
+    }
+
+    fn read_value(&self) -> Result<Value> {
+        crypto::decrypt_value(self.read_raw_value()?)
+    }
+
+    /// Creates the file the database is written to before it is renamed over the database.
+    /// It gets permissions of the database, or is readable only by the current user
+    /// when there is no database yet.
+    fn create_temp_file(&self, temp_location: &str) -> Result<File> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = match options.open(temp_location) {
+            // Left over from a run which was interrupted while writing
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                fs::remove_file(temp_location)?;
+                options.open(temp_location)?
+            }
+            result => result?,
+        };
+        if let Ok(metadata) = fs::metadata(self.location.as_str()) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        Ok(file)
+    }
+
+    /// Writes the value, encrypting it if salt is provided
+    fn write_value(&self, value: &Value, salt: Option<&str>) -> Result<()> {
+        let mut contents = match salt {
+            Some(salt) => serde_json::to_vec_pretty(&crypto::encrypt(
+                &serde_json::to_vec_pretty(value)?,
+                salt,
+            )?)?,
+            None => serde_json::to_vec_pretty(value)?,
+        };
+        contents.push(b'\n');
+        // Database is written next to the original and then renamed over it,
+        // so it is never left truncated or half-written
+        let temp_location = format!("{}.{}.tmp", self.location, std::process::id());
+        let write_result = (|| {
+            let file = self.create_temp_file(temp_location.as_str())?;
+            let mut db_writer = BufWriter::new(file);
+            db_writer.write_all(&contents)?;
+            db_writer.flush()?;
+            db_writer.get_ref().sync_all()?;
+            fs::rename(temp_location.as_str(), self.location.as_str()).map_err(Error::from)
+        })();
+        if write_result.is_err() {
+            let _ = fs::remove_file(temp_location.as_str());
+        }
+        write_result
+    }
+
+    /// Rewrites the file encrypted with the given salt, or decrypted if there is no salt.
+    /// Contents are kept as they are, without schema upgrades.
+    pub fn set_encryption(&self, salt: Option<&str>) -> Result<()> {
+        let value = self.read_value()?;
+        self.write_value(&value, salt)
+    }
+}
+
+impl DbStorage for JsonStorage {
+    fn location(&self) -> &str {
+        self.location.as_str()
+    }
+
+    fn backend(&self) -> DbBackend {
+        DbBackend::Json
+    }
+
+    fn schema_version(&self) -> Result<u32> {
+        migrations::schema_version(&self.read_value()?)
+    }
+
+    fn encryption_salt(&self) -> Result<Option<String>> {
+        if !self.exists() {
+            return Ok(None);
+        }
+        let value = self.read_raw_value()?;
+        if !crypto::is_envelope(&value) {
+            return Ok(None);
+        }
+        let envelope: crypto::Envelope = serde_json::from_value(value)?;
+        Ok(Some(envelope.salt))
+    }
+
+    fn load(&self) -> Result<TaxDb> {
+        if !self.exists() {
+            return Ok(TaxDb::default());
+        }
+        let mut db_value = self.read_value()?;
+        migrations::migrate(&mut db_value)?;
+        serde_json::from_value(db_value).map_err(Error::from)
+    }
+
+    fn save(&self, tax_db: &TaxDb) -> Result<()> {
+        super::backup_before_migration(self)?;
+        // Encrypted database stays encrypted
+        let salt = self.encryption_salt()?;
+        self.write_value(&serde_json::to_value(tax_db)?, salt.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let location =
+            std::env::temp_dir().join(format!("fbihtax-permissions-{}.json", std::process::id()));
+        let storage = JsonStorage::new(location.to_str().unwrap());
+        let mode = || fs::metadata(&location).unwrap().permissions().mode() & 0o777;
+
+        storage.save(&TaxDb::default()).unwrap();
+        assert_eq!(0o600, mode());
+        for expected in [0o640, 0o600] {
+            fs::set_permissions(&location, fs::Permissions::from_mode(expected)).unwrap();
+            storage.save(&TaxDb::default()).unwrap();
+            assert_eq!(expected, mode());
+        }
+
+        fs::remove_file(&location).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    fs::{File, OpenOptions},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+
+use crate::error::{Error, Result, UserErrorKind};
+
+/// How long to wait for another process to release the database
+static LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+static LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Advisory lock on the database, released when dropped.
+/// Lock is held on a separate file, since database file itself gets replaced on each write.
+pub struct DbLock {
+    file: File,
+}
+
+impl DbLock {
+    pub fn acquire(db_location: &str) -> Result<DbLock> {
+        let lock_location = format!("{}.lock", db_location);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_location)?;
+        if file.try_lock_exclusive().is_ok() {
+            return Ok(DbLock { file });
+        }
+
+        // Printed to stderr, so it doesn't end up in listings written to stdout
+        eprintln!(
+            "Database {} is in use by another process. Waiting for it to be released",
+            db_location
+        );
+        let started = Instant::now();
+        while started.elapsed() < LOCK_TIMEOUT {
+            thread::sleep(LOCK_RETRY_INTERVAL);
+            if file.try_lock_exclusive().is_ok() {
+                return Ok(DbLock { file });
+            }
+        }
+        Err(Error::UserError(UserErrorKind::Generic(format!(
+            "Database {} is still in use by another process after {} seconds. If no other fbihtax process is running, try again",
+            db_location,
+            LOCK_TIMEOUT.as_secs()
+        ))))
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_is_exclusive_test() {
+        let location = std::env::temp_dir().join(format!("fbihtax-lock-{}", std::process::id()));
+        let location = location.to_str().unwrap();
+        let lock = DbLock::acquire(location).unwrap();
+
+        let other = File::open(format!("{}.lock", location)).unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+        drop(lock);
+        assert!(other.try_lock_exclusive().is_ok());
+        other.unlock().unwrap();
+
+        std::fs::remove_file(format!("{}.lock", location)).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+pub mod backup;
+pub mod journal;
+mod json;
+mod lock;
+mod sqlite;
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    db::{migrations, AmsFilter, AmsInfo, GpdInfo, TaxDb},
+    error::{Error, Result, UserErrorKind},
+};
+
+pub use self::{
+    backup::BackedUpStorage, journal::JournaledStorage, json::JsonStorage, lock::DbLock,
+    sqlite::SqliteStorage,
+};
+
+/// Extensions which select SQLite backend when no backend is configured
+static SQLITE_EXTENSIONS: [&str; 3] = ["db", "sqlite", "sqlite3"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    Json,
+    Sqlite,
+}
+
+impl DbBackend {
+    /// Picks backend based on the database file extension
+    pub fn from_location(db_location: &str) -> DbBackend {
+        let extension = Path::new(db_location)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension {
+            Some(ext) if SQLITE_EXTENSIONS.contains(&ext.as_str()) => DbBackend::Sqlite,
+            _ => DbBackend::Json,
+        }
+    }
+}
+
+impl std::fmt::Display for DbBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            DbBackend::Json => "json",
+            DbBackend::Sqlite => "sqlite",
+        })
+    }
+}
+
+impl std::str::FromStr for DbBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DbBackend::Json),
+            "sqlite" => Ok(DbBackend::Sqlite),
+            _ => Err("Unknown database backend passed!".to_string()),
+        }
+    }
+}
+
+pub trait DbStorage {
+    /// Location of the database this storage reads and writes
+    fn location(&self) -> &str;
+
+    fn backend(&self) -> DbBackend;
+
+    fn exists(&self) -> bool {
+        Path::new(self.location()).exists()
+    }
+
+    /// Schema version of the stored database, without migrating it
+    fn schema_version(&self) -> Result<u32>;
+
+    /// Salt of the passphrase-derived key, if the database is encrypted
+    fn encryption_salt(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Loads the database, upgrading it to the current schema version if needed.
+    /// Provides an empty database if it does not exist yet.
+    fn load(&self) -> Result<TaxDb>;
+
+    /// Saves the database. Either the whole database is written or it is left unchanged
+    fn save(&self, tax_db: &TaxDb) -> Result<()>;
+
+    /// Locks the database for the duration of a read-modify-write cycle.
+    /// Waits for other processes holding the lock, failing if it takes too long.
+    fn lock(&self) -> Result<DbLock> {
+        DbLock::acquire(self.location())
+    }
+
+    fn find_ams(&self, filter: &AmsFilter) -> Result<Vec<AmsInfo>> {
+        Ok(self.load()?.find_ams(filter).into_iter().cloned().collect())
+    }
+
+    fn get_ams(&self, id: u32) -> Result<Option<AmsInfo>> {
+        Ok(self.load()?.get_ams(id).cloned())
+    }
+
+    /// Entries whose date can't be parsed, which are left out of all date based queries
+    fn malformed_dates(&self) -> Result<Vec<AmsInfo>> {
+        Ok(self
+            .load()?
+            .malformed_dates()
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Adds the entry to the database and returns its newly assigned id
+    fn insert_ams(&self, ams_info: AmsInfo, invoice_date: String) -> Result<u32> {
+        let mut tax_db = self.load()?;
+        let id = tax_db.add_ams_info(ams_info, invoice_date);
+        self.save(&tax_db)?;
+        Ok(id)
+    }
+
+    /// Replaces the stored entry with the same id
+    fn update_ams(&self, ams_info: &AmsInfo) -> Result<()> {
+        let mut tax_db = self.load()?;
+        *tax_db
+            .get_ams_mut(ams_info.id)
+            .ok_or_else(|| entry_not_found(ams_info.id))? = ams_info.clone();
+        self.save(&tax_db)
+    }
+
+    fn delete_ams(&self, id: u32) -> Result<()> {
+        let mut tax_db = self.load()?;
+        tax_db.remove_ams(id).ok_or_else(|| entry_not_found(id))?;
+        self.save(&tax_db)
+    }
+
+    /// Stores GPD result for the year, replacing the previous one
+    fn save_gpd(&self, gpd_info: GpdInfo, year: String) -> Result<()> {
+        let mut tax_db = self.load()?;
+        tax_db.add_gpd_info(gpd_info, year);
+        self.save(&tax_db)
+    }
+}
+
+fn entry_not_found(id: u32) -> Error {
+    Error::UserError(UserErrorKind::Generic(format!(
+        "No entry found with id: {}",
+        id
+    )))
+}
+
+pub fn storage_for(db_location: &str, backend: Option<DbBackend>) -> Box<dyn DbStorage> {
+    match backend.unwrap_or_else(|| DbBackend::from_location(db_location)) {
+        DbBackend::Json => Box::new(JsonStorage::new(db_location)),
+        DbBackend::Sqlite => Box::new(SqliteStorage::new(db_location)),
+    }
+}
+
+/// Storage for the database configured in `dbLocation` and `dbBackend`,
+/// backed up before each write, with all changes recorded in the journal
+pub fn open_storage(config: &Config) -> Box<dyn DbStorage> {
+    Box::new(JournaledStorage {
+        inner: Box::new(BackedUpStorage::new(
+            storage_for(config.db_location.as_str(), config.db_backend),
+            config.backup_count,
+        )),
+        command: std::env::args().skip(1).collect::<Vec<String>>().join(" "),
+    })
+}
+
+/// Encrypts JSON database with the given salt, or decrypts it if there is no salt.
+/// Its backups (including the ones made before schema upgrades) and journal are
+/// encrypted or decrypted as well.
+pub fn set_json_encryption(db_location: &str, salt: Option<&str>) -> Result<()> {
+    JsonStorage::new(db_location).set_encryption(salt)?;
+    let backups = backup::list_backups(db_location)?
+        .into_iter()
+        .map(|backup| backup.path)
+        .chain(migration_backups(db_location)?);
+    for backup in backups {
+        JsonStorage::new(backup.to_str().unwrap_or_default()).set_encryption(salt)?;
+    }
+    journal::set_journal_encryption(db_location, salt)
+}
+
+fn migration_backup_location(db_location: &str, version: u32) -> String {
+    format!("{}.v{}.bak", db_location, version)
+}
+
+/// Backups made before schema upgrades, named `<dbLocation>.v<version>.bak`
+fn migration_backups(db_location: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(db_location);
+    let prefix = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => format!("{}.v", name),
+        None => return Ok(Vec::new()),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let version = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(prefix.as_str()))
+            .and_then(|name| name.strip_suffix(".bak"));
+        if version
+            .and_then(|version| version.parse::<u32>().ok())
+            .is_some()
+        {
+            backups.push(path);
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// Backs up the database file before it gets overwritten by the current schema version.
+/// Databases are upgraded in memory when loaded, so the file only changes once it is saved.
+fn backup_before_migration(storage: &dyn DbStorage) -> Result<()> {
+    if !storage.exists() {
+        return Ok(());
+    }
+    let version = storage.schema_version()?;
+    if version >= migrations::CURRENT_SCHEMA_VERSION {
+        return Ok(());
+    }
+    let backup_location = migration_backup_location(storage.location(), version);
+    std::fs::copy(storage.location(), &backup_location)?;
+    // Printed to stderr, since database can be saved by commands writing their output to stdout
+    eprintln!(
+        "Upgrading database schema from version {} to {}. Backup saved to: {}",
+        version,
+        migrations::CURRENT_SCHEMA_VERSION,
+        backup_location
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_from_location_test() {
+        let pairs = [
+            ("fbihtax.db.json", DbBackend::Json),
+            ("fbihtax.db", DbBackend::Sqlite),
+            ("taxes.sqlite", DbBackend::Sqlite),
+            ("taxes.SQLITE3", DbBackend::Sqlite),
+            ("taxes", DbBackend::Json),
+        ];
+
+        for (location, expected) in pairs {
+            assert_eq!(expected, DbBackend::from_location(location));
+        }
+    }
+
+    #[test]
+    fn backup_before_migration_test() {
+        let dir = std::env::temp_dir().join(format!("fbihtax-migration-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let location = dir.join("db.json");
+        let backup_location = dir.join("db.json.v1.bak");
+        std::fs::write(
+            &location,
+            r#"{ "ams": { "2021-02-01": { "incomeTotal": "100", "taxPaid": "10" } } }"#,
+        )
+        .unwrap();
+        let storage = JsonStorage::new(location.to_str().unwrap());
+
+        // Loading upgrades the database only in memory
+        let tax_db = storage.load().unwrap();
+        assert_eq!(1, tax_db.ams.len());
+        assert!(!backup_location.exists());
+        assert_eq!(1, storage.schema_version().unwrap());
+
+        storage.save(&tax_db).unwrap();
+        assert!(backup_location.exists());
+        assert_eq!(
+            migrations::CURRENT_SCHEMA_VERSION,
+            storage.schema_version().unwrap()
+        );
+        assert_eq!(
+            vec![backup_location.clone()],
+            migration_backups(location.to_str().unwrap()).unwrap()
+        );
+        std::fs::remove_file(&backup_location).unwrap();
+        storage.save(&tax_db).unwrap();
+        assert!(!backup_location.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+use rusqlite::{params, Connection, OptionalExtension, Params};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    dates,
+    db::{self, migrations, AmsFilter, AmsInfo, GpdInfo, TaxDb},
+    error::{Error, Result},
+};
+
+use super::{DbBackend, DbStorage};
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS ams (
+        id INTEGER PRIMARY KEY,
+        date TEXT NOT NULL,
+        client TEXT,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS ams_date ON ams (date);
+    CREATE TABLE IF NOT EXISTS gpd (
+        year TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+";
+
+static SCHEMA_VERSION_KEY: &str = "schemaVersion";
+static NEXT_AMS_ID_KEY: &str = "nextAmsId";
+
+/// Stores the database in SQLite. Fields used for querying are kept in their own
+/// columns, while each record is stored as JSON, so records go through the same
+/// schema migrations as the JSON backend.
+pub struct SqliteStorage {
+    location: String,
+}
+
+impl SqliteStorage {
+    pub fn new(location: &str) -> SqliteStorage {
+        SqliteStorage {
+            location: location.to_string(),
+        }
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let connection = Connection::open(self.location.as_str())?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+
+    fn read_meta(connection: &Connection, key: &str) -> Result<Option<String>> {
+        connection
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Error::from)
+    }
+
+    fn write_meta(connection: &Connection, key: &str, value: String) -> Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    fn read_next_ams_id(connection: &Connection) -> Result<Option<u32>> {
+        SqliteStorage::read_meta(connection, NEXT_AMS_ID_KEY)?
+            .map(|next_ams_id| {
+                next_ams_id.parse().map_err(|_| {
+                    Error::UnexpectedCondition(format!("Invalid next AMS id: {}", next_ams_id))
+                })
+            })
+            .transpose()
+    }
+
+    fn read_schema_version(connection: &Connection) -> Result<u32> {
+        match SqliteStorage::read_meta(connection, SCHEMA_VERSION_KEY)? {
+            Some(version) => version.parse().map_err(|_| {
+                Error::UnexpectedCondition(format!("Invalid database schema version: {}", version))
+            }),
+            None => Ok(migrations::CURRENT_SCHEMA_VERSION),
+        }
+    }
+
+    /// Reads the database into the same JSON structure used by the JSON backend
+    fn read_value(connection: &Connection) -> Result<Value> {
+        let version = SqliteStorage::read_schema_version(connection)?;
+        let mut statement = connection.prepare("SELECT data FROM ams ORDER BY id")?;
+        let ams = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<Vec<Value>>>()?;
+        let mut statement = connection.prepare("SELECT year, data FROM gpd ORDER BY year")?;
+        let gpd = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|row| {
+                let (year, data) = row?;
+                Ok((year, serde_json::from_str(&data)?))
+            })
+            .collect::<Result<Map<String, Value>>>()?;
+        let mut value = json!({
+            "schemaVersion": version,
+            "ams": ams,
+            "gpd": gpd,
+        });
+        if let Some(next_ams_id) = SqliteStorage::read_next_ams_id(connection)? {
+            value["nextAmsId"] = json!(next_ams_id);
+        }
+        Ok(value)
+    }
+
+    fn write_ams(connection: &Connection, ams_info: &AmsInfo) -> Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO ams (id, date, client, data) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                ams_info.id,
+                ams_info.date,
+                ams_info.client,
+                serde_json::to_string(ams_info)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn write_gpd(connection: &Connection, key: &str, gpd_info: &GpdInfo) -> Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO gpd (year, data) VALUES (?1, ?2)",
+            params![key, serde_json::to_string(gpd_info)?],
+        )?;
+        Ok(())
+    }
+
+    fn query_ams<P: Params>(connection: &Connection, sql: &str, params: P) -> Result<Vec<AmsInfo>> {
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| row.get::<_, String>(0))?;
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    /// Connection to the database with the current schema, if it exists. Older databases
+    /// are only read through `load`, which migrates them.
+    fn connect_if_current(&self) -> Result<Option<Connection>> {
+        if !self.exists() {
+            return Ok(None);
+        }
+        let connection = self.connect()?;
+        if SqliteStorage::read_schema_version(&connection)? != migrations::CURRENT_SCHEMA_VERSION {
+            return Ok(None);
+        }
+        Ok(Some(connection))
+    }
+
+    /// Connection for writing single records. Database is created or upgraded to the
+    /// current schema version first, so records are always written in the current format.
+    fn connect_for_update(&self) -> Result<Connection> {
+        match self.connect_if_current()? {
+            Some(connection) => Ok(connection),
+            None => {
+                self.save(&self.load()?)?;
+                self.connect()
+            }
+        }
+    }
+}
+
+impl DbStorage for SqliteStorage {
+    fn location(&self) -> &str {
+        self.location.as_str()
+    }
+
+    fn backend(&self) -> DbBackend {
+        DbBackend::Sqlite
+    }
+
+    fn schema_version(&self) -> Result<u32> {
+        SqliteStorage::read_schema_version(&self.connect()?)
+    }
+
+    fn load(&self) -> Result<TaxDb> {
+        if !self.exists() {
+            return Ok(TaxDb::default());
+        }
+        let connection = self.connect()?;
+        let mut db_value = SqliteStorage::read_value(&connection)?;
+        migrations::migrate(&mut db_value)?;
+        serde_json::from_value(db_value).map_err(Error::from)
+    }
+
+    fn save(&self, tax_db: &TaxDb) -> Result<()> {
+        super::backup_before_migration(self)?;
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM ams", [])?;
+        transaction.execute("DELETE FROM gpd", [])?;
+        for ams_info in tax_db.ams.iter() {
+            SqliteStorage::write_ams(&transaction, ams_info)?;
+        }
+        for (key, gpd_info) in tax_db.gpd.iter() {
+            SqliteStorage::write_gpd(&transaction, key, gpd_info)?;
+        }
+        SqliteStorage::write_meta(
+            &transaction,
+            SCHEMA_VERSION_KEY,
+            tax_db.schema_version.to_string(),
+        )?;
+        SqliteStorage::write_meta(
+            &transaction,
+            NEXT_AMS_ID_KEY,
+            tax_db.next_ams_id.to_string(),
+        )?;
+        transaction.commit().map_err(Error::from)
+    }
+
+    fn get_ams(&self, id: u32) -> Result<Option<AmsInfo>> {
+        match self.connect_if_current()? {
+            Some(connection) => Ok(SqliteStorage::query_ams(
+                &connection,
+                "SELECT data FROM ams WHERE id = ?1",
+                params![id],
+            )?
+            .pop()),
+            None => Ok(self.load()?.get_ams(id).cloned()),
+        }
+    }
+
+    fn malformed_dates(&self) -> Result<Vec<AmsInfo>> {
+        let connection = match self.connect_if_current()? {
+            Some(connection) => connection,
+            None => {
+                return Ok(self
+                    .load()?
+                    .malformed_dates()
+                    .into_iter()
+                    .cloned()
+                    .collect())
+            }
+        };
+        // SQLite normalizes valid dates to YYYY-MM-DD, so any other value is at least suspicious
+        let suspicious = SqliteStorage::query_ams(
+            &connection,
+            "SELECT data FROM ams WHERE date(date) IS NOT date ORDER BY id",
+            [],
+        )?;
+        Ok(suspicious
+            .into_iter()
+            .filter(|ams_info| dates::parse_date(&ams_info.date).is_err())
+            .collect())
+    }
+
+    fn insert_ams(&self, ams_info: AmsInfo, invoice_date: String) -> Result<u32> {
+        let mut connection = self.connect_for_update()?;
+        let transaction = connection.transaction()?;
+        let highest_id: Option<u32> =
+            transaction.query_row("SELECT MAX(id) FROM ams", [], |row| row.get(0))?;
+        let id = db::new_ams_id(
+            SqliteStorage::read_next_ams_id(&transaction)?.unwrap_or(1),
+            highest_id.unwrap_or(0),
+        );
+        SqliteStorage::write_ams(&transaction, &ams_info.stored_as(id, invoice_date))?;
+        SqliteStorage::write_meta(&transaction, NEXT_AMS_ID_KEY, (id + 1).to_string())?;
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    fn update_ams(&self, ams_info: &AmsInfo) -> Result<()> {
+        let connection = self.connect_for_update()?;
+        let updated = connection.execute(
+            "UPDATE ams SET date = ?2, client = ?3, data = ?4 WHERE id = ?1",
+            params![
+                ams_info.id,
+                ams_info.date,
+                ams_info.client,
+                serde_json::to_string(ams_info)?
+            ],
+        )?;
+        if updated == 0 {
+            return Err(super::entry_not_found(ams_info.id));
+        }
+        Ok(())
+    }
+
+    fn delete_ams(&self, id: u32) -> Result<()> {
+        let connection = self.connect_for_update()?;
+        if connection.execute("DELETE FROM ams WHERE id = ?1", params![id])? == 0 {
+            return Err(super::entry_not_found(id));
+        }
+        Ok(())
+    }
+
+    fn save_gpd(&self, gpd_info: GpdInfo, year: String) -> Result<()> {
+        let connection = self.connect_for_update()?;
+        SqliteStorage::write_gpd(
+            &connection,
+            &db::gpd_key(&year, gpd_info.taxpayer.as_deref()),
+            &gpd_info,
+        )
+    }
+
+    fn find_ams(&self, filter: &AmsFilter) -> Result<Vec<AmsInfo>> {
+        if !self.exists() {
+            return Ok(Vec::new());
+        }
+        let connection = match self.connect_if_current()? {
+            Some(connection) => connection,
+            None => return Ok(self.load()?.find_ams(filter).into_iter().cloned().collect()),
+        };
+        // Dates are only narrowed down to the bounds of the filter here
+        let bounds = filter.period().and_then(|period| period.bounds());
+        let from = [bounds.map(|(start, _)| start), filter.from]
+            .into_iter()
+            .flatten()
+            .max();
+        let to = [bounds.map(|(_, end)| end), filter.to]
+            .into_iter()
+            .flatten()
+            .min();
+        let mut statement = connection.prepare(
+            "SELECT data FROM ams
+            WHERE (?1 IS NULL OR date >= ?1)
+            AND (?2 IS NULL OR date <= ?2)
+            ORDER BY date, id",
+        )?;
+        let rows = statement.query_map(
+            params![
+                from.map(|from| from.to_string()),
+                to.map(|to| to.to_string())
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+        let mut result = Vec::new();
+        for data in rows {
+            let ams_info: AmsInfo = serde_json::from_str(&data?)?;
+            // Client matching and date validation are left to the filter itself
+            if filter.matches(&ams_info) {
+                result.push(ams_info);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::db::GpdInfo;
+
+    use super::*;
+
+    #[test]
+    fn save_and_load_test() {
+        let location = std::env::temp_dir().join(format!("fbihtax-test-{}.db", std::process::id()));
+        let storage = SqliteStorage::new(location.to_str().unwrap());
+        let mut tax_db = TaxDb::default();
+        for (date, client) in [("2021-02-10", "Acme"), ("2021-03-10", "Other")] {
+            tax_db.add_ams_info(
+                AmsInfo {
+                    income_total: dec!(1000),
+                    tax_paid: dec!(96),
+                    client: Some(client.to_string()),
+                    ..Default::default()
+                },
+                date.to_string(),
+            );
+        }
+        tax_db.add_gpd_info(
+            GpdInfo {
+                loss: dec!(150),
+                ..Default::default()
+            },
+            "2020".to_string(),
+        );
+        storage.save(&tax_db).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(2, loaded.ams.len());
+        assert_eq!(3, loaded.next_ams_id);
+        assert_eq!(dec!(150), loaded.gpd["2020"].loss);
+
+        let found = storage
+            .find_ams(&AmsFilter {
+                month: Some(3),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!(Some("Other".to_string()), found[0].client);
+
+        std::fs::remove_file(location).unwrap();
+    }
+
+    #[test]
+    fn single_records_test() {
+        let location =
+            std::env::temp_dir().join(format!("fbihtax-records-{}.db", std::process::id()));
+        let storage = SqliteStorage::new(location.to_str().unwrap());
+        let ams_info = |income_total| AmsInfo {
+            income_total,
+            ..Default::default()
+        };
+        assert_eq!(
+            1,
+            storage
+                .insert_ams(ams_info(dec!(100)), "2021-02-10".to_string())
+                .unwrap()
+        );
+        assert_eq!(
+            2,
+            storage
+                .insert_ams(ams_info(dec!(200)), "2021-02-30".to_string())
+                .unwrap()
+        );
+        storage.delete_ams(2).unwrap();
+        assert!(storage.delete_ams(2).is_err());
+        let id = storage
+            .insert_ams(ams_info(dec!(300)), "2021-13-01".to_string())
+            .unwrap();
+        assert_eq!(3, id);
+
+        let mut entry = storage.get_ams(1).unwrap().unwrap();
+        assert_eq!(Some("2021-03-10".to_string()), entry.due_date);
+        entry.paid = true;
+        storage.update_ams(&entry).unwrap();
+        storage
+            .save_gpd(
+                GpdInfo {
+                    loss: dec!(50),
+                    ..Default::default()
+                },
+                "2021".to_string(),
+            )
+            .unwrap();
+
+        let malformed = storage.malformed_dates().unwrap();
+        assert_eq!(
+            vec![3],
+            malformed.iter().map(|v| v.id).collect::<Vec<u32>>()
+        );
+        let loaded = storage.load().unwrap();
+        assert_eq!(2, loaded.ams.len());
+        assert!(loaded.get_ams(1).unwrap().paid);
+        assert_eq!(4, loaded.next_ams_id);
+        assert_eq!(dec!(50), loaded.gpd["2021"].loss);
+
+        std::fs::remove_file(location).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{dates, forms::formutils::format_money_value, taxcalculator};
+
+use super::{AmsInfo, TaxDb};
+
+/// Entries dated before this year are considered mistakes
+static EARLIEST_PLAUSIBLE_YEAR: i32 = 2000;
+/// Allowed difference between stored and recalculated amounts, to account for rounding
+static AMOUNT_TOLERANCE: Decimal = dec!(0.01);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Record is most likely wrong
+    Error,
+    /// Record might be wrong and should be checked
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Record the problem was found in, like "AMS 3" or "GPD 2021"
+    pub record: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn to_dict(&self) -> HashMap<String, String> {
+        let mut result = HashMap::new();
+        result.insert("severity".to_string(), self.severity.to_string());
+        result.insert("record".to_string(), self.record.clone());
+        result.insert("problem".to_string(), self.message.clone());
+        result
+    }
+}
+
+fn amounts_differ(stored: Decimal, expected: Decimal) -> bool {
+    (stored.round_dp(2) - expected.round_dp(2)).abs() > AMOUNT_TOLERANCE
+}
+
+fn ams_record(ams_info: &AmsInfo) -> String {
+    format!("AMS {}", ams_info.id)
+}
+
+fn verify_amounts(ams_info: &AmsInfo, problems: &mut Vec<Problem>) {
+    let mut error = |message: String| {
+        problems.push(Problem {
+            severity: Severity::Error,
+            record: ams_record(ams_info),
+            message,
+        })
+    };
+    if let (Some(income), Some(deduction_percentage)) =
+        (ams_info.income, ams_info.deduction_percentage)
+    {
+        let expected = taxcalculator::income_after_deduction(income, deduction_percentage);
+        if amounts_differ(ams_info.income_total, expected) {
+            error(format!(
+                "Income after deduction is {}, but {} was expected for income {} with {}% deduction",
+                format_money_value(ams_info.income_total),
+                format_money_value(expected),
+                format_money_value(income),
+                deduction_percentage
+            ));
+        }
+    }
+    if let Some(health_insurance) = ams_info.health_insurance {
+        let expected = taxcalculator::health_insurance_part(ams_info.income_total);
+        if amounts_differ(health_insurance, expected) {
+            error(format!(
+                "Health insurance is {}, but {} was expected",
+                format_money_value(health_insurance),
+                format_money_value(expected)
+            ));
+        }
+    }
+    let expected = taxcalculator::tax_amount(ams_info.income_total);
+    if amounts_differ(ams_info.tax_paid, expected) {
+        error(format!(
+            "Tax is {}, but {} was expected",
+            format_money_value(ams_info.tax_paid),
+            format_money_value(expected)
+        ));
+    }
+}
+
+fn verify_date(ams_info: &AmsInfo, today: NaiveDate, problems: &mut Vec<Problem>) {
+    let message = match dates::parse_date(&ams_info.date) {
+        Err(_) => format!("Invalid date: {}", ams_info.date),
+        Ok(date) if date.year() < EARLIEST_PLAUSIBLE_YEAR => {
+            format!("Date is implausibly old: {}", ams_info.date)
+        }
+        Ok(date) if date > today => format!("Date is in the future: {}", ams_info.date),
+        Ok(_) => return,
+    };
+    problems.push(Problem {
+        severity: Severity::Error,
+        record: ams_record(ams_info),
+        message,
+    });
+}
+
+fn verify_duplicates(tax_db: &TaxDb, problems: &mut Vec<Problem>) {
+    for (index, ams_info) in tax_db.ams.iter().enumerate() {
+        for other in tax_db.ams.iter().skip(index + 1) {
+            if ams_info.is_duplicate_of(other) {
+                problems.push(Problem {
+                    severity: Severity::Error,
+                    record: ams_record(other),
+                    message: format!("Loaded from the same file as AMS {}", ams_info.id),
+                });
+            } else if ams_info.is_similar_to(other) {
+                // Separate payments of the same amount on the same day are possible
+                problems.push(Problem {
+                    severity: Severity::Warning,
+                    record: ams_record(other),
+                    message: format!("Possible duplicate of AMS {}", ams_info.id),
+                });
+            }
+        }
+    }
+}
+
+fn is_plausible(date: NaiveDate, today: NaiveDate) -> bool {
+    date.year() >= EARLIEST_PLAUSIBLE_YEAR && date <= today
+}
+
+/// Months without any entries between the first and the last recorded month
+fn verify_gaps(tax_db: &TaxDb, today: NaiveDate, problems: &mut Vec<Problem>) {
+    let months: BTreeSet<(i32, u32)> = tax_db
+        .ams
+        .iter()
+        .filter_map(|ams_info| dates::parse_date(&ams_info.date).ok())
+        .filter(|date| is_plausible(*date, today))
+        .map(|date| (date.year(), date.month()))
+        .collect();
+    let (first, last) = match (months.iter().next(), months.iter().next_back()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return,
+    };
+    let mut month = first;
+    while month < last {
+        if !months.contains(&month) {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                record: format!("{}-{:02}", month.0, month.1),
+                message: "No AMS entries in this month".to_string(),
+            });
+        }
+        month = if month.1 == 12 {
+            (month.0 + 1, 1)
+        } else {
+            (month.0, month.1 + 1)
+        };
+    }
+}
+
+/// Income reported in GPD results against AMS entries currently in the database
+fn verify_gpd(tax_db: &TaxDb, problems: &mut Vec<Problem>) {
+    let mut keys: Vec<&String> = tax_db.gpd.keys().collect();
+    keys.sort();
+    for key in keys {
+        let gpd_info = &tax_db.gpd[key];
+        let period = super::gpd_year(key).parse::<i32>().ok().and_then(|y| {
+            let start = match &gpd_info.period_start {
+                Some(date) => dates::parse_date(date).ok(),
+                None => dates::year_start(y),
+            };
+            let end = match &gpd_info.period_end {
+                Some(date) => dates::parse_date(date).ok(),
+                None => dates::year_end(y),
+            };
+            start.zip(end)
+        });
+        let ((start, end), ams) = match (period, &gpd_info.ams) {
+            (Some(period), Some(ams)) => (period, ams),
+            _ => continue,
+        };
+        let income = tax_db.total_income(
+            &dates::Period::Range(start, end),
+            gpd_info.taxpayer.as_deref(),
+        );
+        if amounts_differ(ams.income, income) {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                record: format!("GPD {}", key),
+                message: format!(
+                    "GPD form reports AMS income of {}, but AMS entries for the period add up to {}",
+                    format_money_value(ams.income),
+                    format_money_value(income)
+                ),
+            });
+        }
+    }
+}
+
+/// Checks the database for records which are likely wrong
+pub fn verify(tax_db: &TaxDb, today: NaiveDate) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut entries: Vec<&AmsInfo> = tax_db.ams.iter().collect();
+    entries.sort_by_key(|ams_info| ams_info.id);
+    for ams_info in entries {
+        verify_date(ams_info, today, &mut problems);
+        verify_amounts(ams_info, &mut problems);
+    }
+    verify_duplicates(tax_db, &mut problems);
+    verify_gaps(tax_db, today, &mut problems);
+    verify_gpd(tax_db, &mut problems);
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ams_info(income_total: Decimal) -> AmsInfo {
+        AmsInfo {
+            income_total,
+            health_insurance: Some(taxcalculator::health_insurance_part(income_total)),
+            tax_paid: taxcalculator::tax_amount(income_total),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn verify_test() {
+        let today = NaiveDate::from_ymd(2021, 12, 31);
+        let mut db = TaxDb::default();
+        db.add_ams_info(ams_info(dec!(800)), "2021-01-10".to_string());
+        assert!(verify(&db, today).is_empty());
+
+        let wrong_tax = db.add_ams_info(
+            AmsInfo {
+                tax_paid: dec!(10),
+                ..ams_info(dec!(800))
+            },
+            "2021-03-10".to_string(),
+        );
+        db.add_ams_info(ams_info(dec!(800)), "2021-03-10".to_string());
+        db.add_ams_info(ams_info(dec!(100)), "21-04-10".to_string());
+        db.add_ams_info(ams_info(dec!(100)), "2030-01-10".to_string());
+        for _ in 0..2 {
+            db.add_ams_info(
+                AmsInfo {
+                    source_fingerprint: Some("abc".to_string()),
+                    ..ams_info(dec!(500))
+                },
+                "2021-03-10".to_string(),
+            );
+        }
+
+        let problems = verify(&db, today);
+        let records: Vec<(Severity, &str)> = problems
+            .iter()
+            .map(|p| (p.severity, p.record.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Severity::Error, format!("AMS {}", wrong_tax).as_str()),
+                (Severity::Error, "AMS 4"),
+                (Severity::Error, "AMS 5"),
+                (Severity::Warning, "AMS 3"),
+                (Severity::Error, "AMS 7"),
+                (Severity::Warning, "2021-02"),
+            ],
+            records
+        );
+    }
+}
@@ -2,11 +2,14 @@ use std::{error, fmt::Display, io};
 
 use crate::format::OutputFormat;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Network(reqwest::Error),
     Json(serde_json::Error),
+    Csv(csv::Error),
+    Database(rusqlite::Error),
     Pdf(PdfErrorKind),
     UserError(UserErrorKind),
     UnexpectedCondition(String),
@@ -16,6 +19,7 @@ pub enum Error {
 pub enum UserErrorKind {
     UnsupportedOutputFormat(OutputFormat),
     MissingConfig(String, String),
+    UnsupportedSchemaVersion(u32, u32),
     Generic(String),
 }
 
@@ -23,6 +27,25 @@ pub enum UserErrorKind {
 pub enum PdfErrorKind {
     Value(pdf_forms::ValueError),
     Load(pdf_forms::LoadError),
+    Document(lopdf::Error),
+    Pdftk(PdftkErrorKind),
+}
+
+#[derive(Debug)]
+pub enum PdftkErrorKind {
+    /// pdftk could not be executed from the configured path
+    NotFound(String, io::Error),
+    /// Executable at the configured path doesn't report itself as pdftk
+    NotPdftk(String, String),
+    Failed {
+        version: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// pdftk exited successfully, without writing the filled form
+    NoOutput(String, String),
+    /// Installed pdftk version doesn't support `replacement_font`
+    ReplacementFontUnsupported(String),
 }
 
 impl error::Error for Error {
@@ -30,9 +53,14 @@ impl error::Error for Error {
         match self {
             Error::Io(err) => Some(err),
             Error::Json(err) => Some(err),
+            Error::Csv(err) => Some(err),
+            Error::Database(err) => Some(err),
             Error::Pdf(err) => match err {
                 PdfErrorKind::Value(inner) => Some(inner),
                 PdfErrorKind::Load(inner) => Some(inner),
+                PdfErrorKind::Document(inner) => Some(inner),
+                PdfErrorKind::Pdftk(PdftkErrorKind::NotFound(_, inner)) => Some(inner),
+                PdfErrorKind::Pdftk(_) => None,
             },
             Error::Network(err) => Some(err),
             _ => None,
@@ -49,6 +77,8 @@ impl Display for Error {
         match self {
             Error::Io(err) => err.fmt(f),
             Error::Json(err) => err.fmt(f),
+            Error::Csv(err) => err.fmt(f),
+            Error::Database(err) => err.fmt(f),
             Error::Pdf(err) => err.fmt(f),
             Error::Network(err) => err.fmt(f),
             Error::UserError(message) => message.fmt(f),
@@ -67,6 +97,12 @@ impl Display for UserErrorKind {
                 "Missing {}. Try to provide it with {}",
                 config_name, argument
             )),
+            UserErrorKind::UnsupportedSchemaVersion(version, supported) => {
+                f.write_fmt(format_args!(
+                    "Database uses schema version {}, but this version of fbihtax supports up to version {}. Update fbihtax to use this database",
+                    version, supported
+                ))
+            }
             UserErrorKind::Generic(message) => message.fmt(f),
         }
     }
@@ -77,6 +113,43 @@ impl Display for PdfErrorKind {
         match self {
             PdfErrorKind::Value(err) => err.fmt(f),
             PdfErrorKind::Load(err) => err.fmt(f),
+            PdfErrorKind::Document(err) => err.fmt(f),
+            PdfErrorKind::Pdftk(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Display for PdftkErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PdftkErrorKind::NotFound(path, err) => f.write_fmt(format_args!(
+                "Failed to execute pdftk at {} ({}). Ensure it is installed and its path is set as pdf.pdftkPath in .fbihtax.json, or use the native PDF backend",
+                path, err
+            )),
+            PdftkErrorKind::NotPdftk(path, stderr) => f.write_fmt(format_args!(
+                "{} doesn't seem to be pdftk. Check pdf.pdftkPath in .fbihtax.json{}",
+                path,
+                if stderr.is_empty() { String::new() } else { format!(": {}", stderr) }
+            )),
+            PdftkErrorKind::Failed {
+                version,
+                status,
+                stderr,
+            } => f.write_fmt(format_args!(
+                "{} failed with exit status {}{}",
+                version,
+                status.map(|code| code.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                if stderr.is_empty() { String::new() } else { format!(": {}", stderr) }
+            )),
+            PdftkErrorKind::NoOutput(version, stderr) => f.write_fmt(format_args!(
+                "{} exited without writing the filled form{}",
+                version,
+                if stderr.is_empty() { String::new() } else { format!(": {}", stderr) }
+            )),
+            PdftkErrorKind::ReplacementFontUnsupported(version) => f.write_fmt(format_args!(
+                "{} doesn't support replacement fonts, pdftk-java 3.3.0 or newer is needed. Alternatively, use the native PDF backend with a TTF font",
+                version
+            )),
         }
     }
 }
@@ -99,6 +172,18 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        Error::Csv(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Database(err)
+    }
+}
+
 impl From<pdf_forms::ValueError> for Error {
     fn from(err: pdf_forms::ValueError) -> Self {
         Error::Pdf(PdfErrorKind::Value(err))
@@ -111,4 +196,10 @@ impl From<pdf_forms::LoadError> for Error {
     }
 }
 
+impl From<lopdf::Error> for Error {
+    fn from(err: lopdf::Error) -> Self {
+        Error::Pdf(PdfErrorKind::Document(err))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -28,15 +28,13 @@ pub struct FdfDataEntry {
 
 impl FdfData {
     pub fn from_dict(dict: HashMap<String, String>) -> Self {
-        Self {
-            entries: dict
-                .iter()
-                .map(|(k, v)| FdfDataEntry {
-                    title: k.clone(),
-                    value: v.clone(),
-                })
-                .collect(),
+        let mut data = Self {
+            entries: Vec::with_capacity(dict.len()),
+        };
+        for (title, value) in dict {
+            data.add_entry(title, value);
         }
+        data
     }
 
     pub fn add_entry(&mut self, title: String, value: String) {
@@ -71,6 +69,90 @@ pub fn write_fdf(data: FdfData, output_file: String) -> Result<()> {
     Ok(())
 }
 
+/// Reads field values from FDF contents, as written by `write_fdf`
+pub fn parse_fdf(content: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("/T(") {
+        rest = &rest[start + 3..];
+        let (title, after_title) = match read_fdf_string(rest) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        rest = after_title;
+        if let Some(value_rest) = rest.trim_start().strip_prefix("/V(") {
+            if let Some((value, after_value)) = read_fdf_string(value_rest) {
+                result.insert(title, value);
+                rest = after_value;
+            }
+        }
+    }
+    result
+}
+
+/// Reads string up to the closing parenthesis, returning it and the remaining contents
+fn read_fdf_string(content: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut escaped = false;
+    let mut depth = 0;
+    for (index, c) in content.char_indices() {
+        match c {
+            _ if escaped => {
+                value.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            '(' => {
+                depth += 1;
+                value.push(c);
+            }
+            ')' if depth == 0 => return Some((value, &content[index + 1..])),
+            ')' => {
+                depth -= 1;
+                value.push(c);
+            }
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+/// Reads field values from XFDF contents, as written by `write_xfdf`
+pub fn parse_xfdf(content: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<field name=\"") {
+        rest = &rest[start + 13..];
+        let title = match rest.find('"') {
+            Some(end) => {
+                let title = &rest[..end];
+                rest = &rest[end + 1..];
+                title
+            }
+            None => break,
+        };
+        let value = match (rest.find("<value>"), rest.find("</value>")) {
+            (Some(start), Some(end)) if start < end => {
+                let value = &rest[start + 7..end];
+                rest = &rest[end + 8..];
+                value
+            }
+            _ => continue,
+        };
+        result.insert(title.to_string(), unescape_xml(value));
+    }
+    result
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env::temp_dir, io::Read};
@@ -127,8 +209,9 @@ mod tests {
 
         write_fdf(data, output_file.to_str().unwrap().to_string()).unwrap();
 
-        let file = File::open(output_file).unwrap();
-        let file_data: Vec<u8> = file.bytes().map(|x| x.unwrap()).collect();
+        let mut file = File::open(output_file).unwrap();
+        let mut file_data: Vec<u8> = Vec::new();
+        file.read_to_end(&mut file_data).unwrap();
 
         assert_eq!(
             concat!(
@@ -146,4 +229,39 @@ mod tests {
             String::from_utf8(file_data).unwrap()
         );
     }
+
+    #[test]
+    fn parse_fdf_test() {
+        let content = concat!(
+            "%FDF-1.2\n",
+            "1 0 obj<</FDF<< /Fields[\n",
+            "<</T(entry_two)/V(value (two))>>\n",
+            "<</T(test)/V(value\\))>>\n",
+            "] >> >>\n",
+            "endobj\n",
+        );
+        let dict = parse_fdf(content);
+
+        assert_eq!(2, dict.len());
+        assert_eq!("value (two)", dict["entry_two"]);
+        assert_eq!("value)", dict["test"]);
+    }
+
+    #[test]
+    fn parse_xfdf_test() {
+        let content = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n",
+            "<xfdf xmlns=\"http://ns.adobe.com/xfdf/\" xml:space=\"preserve\">\n",
+            "  <fields>\n",
+            "    <field name=\"8 Dr&#382;ava\"><value>BiH</value></field>\n",
+            "    <field name=\"test\"><value>A &amp; B</value></field>\n",
+            "  </fields>\n",
+            "</xfdf>"
+        );
+        let dict = parse_xfdf(content);
+
+        assert_eq!(2, dict.len());
+        assert_eq!("BiH", dict["8 Dr&#382;ava"]);
+        assert_eq!("A & B", dict["test"]);
+    }
 }
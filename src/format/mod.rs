@@ -1,3 +1,4 @@
+pub mod pdftk;
 pub mod printer;
 pub mod utils;
 
@@ -8,6 +9,8 @@ pub enum OutputFormat {
     Xfdf,
     Json,
     Stdout,
+    Table,
+    Csv,
 }
 
 impl std::fmt::Display for OutputFormat {
@@ -18,6 +21,8 @@ impl std::fmt::Display for OutputFormat {
             OutputFormat::Xfdf => "xfdf",
             OutputFormat::Json => "json",
             OutputFormat::Stdout => "stdout",
+            OutputFormat::Table => "table",
+            OutputFormat::Csv => "csv",
         })
     }
 }
@@ -30,6 +35,8 @@ impl std::fmt::Debug for OutputFormat {
             OutputFormat::Xfdf => "xfdf",
             OutputFormat::Json => "json",
             OutputFormat::Stdout => "stdout",
+            OutputFormat::Table => "table",
+            OutputFormat::Csv => "csv",
         })
     }
 }
@@ -44,6 +51,8 @@ impl std::str::FromStr for OutputFormat {
             "xfdf" => Ok(OutputFormat::Xfdf),
             "json" => Ok(OutputFormat::Json),
             "stdout" => Ok(OutputFormat::Stdout),
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
             _ => Err("Unknown format passed!".to_string()),
         }
     }
@@ -65,6 +74,11 @@ mod tests {
             OutputFormat::Stdout,
             OutputFormat::from_str("stdout").unwrap()
         );
+        assert_eq!(
+            OutputFormat::Table,
+            OutputFormat::from_str("table").unwrap()
+        );
+        assert_eq!(OutputFormat::Csv, OutputFormat::from_str("csv").unwrap());
     }
 
     #[test]
@@ -80,6 +94,7 @@ mod tests {
         assert!(OutputFormat::from_str("FDF").is_err());
         assert!(OutputFormat::from_str("XFDF").is_err());
         assert!(OutputFormat::from_str("STDOUT").is_err());
+        assert!(OutputFormat::from_str("CSV").is_err());
     }
 
     #[test]
@@ -0,0 +1,315 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::error::{Error, PdfErrorKind, PdftkErrorKind, Result};
+
+use super::printer::STDOUT_FILE;
+
+/// First pdftk-java version supporting `replacement_font`
+static REPLACEMENT_FONT_VERSION: [u32; 3] = [3, 3, 0];
+
+/// Counter making temporary files unique within a single run
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PdftkVersion {
+    /// First line of `pdftk --version` output
+    pub description: String,
+    /// Version number, as reported by pdftk
+    pub version: Option<String>,
+    /// pdftk-java port, as opposed to the original pdftk
+    pub java: bool,
+}
+
+impl std::fmt::Display for PdftkVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = if self.java { "pdftk-java" } else { "pdftk" };
+        match &self.version {
+            Some(version) => f.write_fmt(format_args!("{} {}", name, version)),
+            None => f.write_fmt(format_args!("{} of unknown version", name)),
+        }
+    }
+}
+
+fn pdftk_error(kind: PdftkErrorKind) -> Error {
+    Error::Pdf(PdfErrorKind::Pdftk(kind))
+}
+
+/// Parts of a version number (e.g. `3.3.2` or `2.02`)
+fn version_parts(word: &str) -> Option<Vec<u32>> {
+    let parts = word
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    Some(parts).filter(|parts| parts.len() >= 2)
+}
+
+impl PdftkVersion {
+    /// Whether this is pdftk-java of at least the given version
+    pub fn java_at_least(&self, minimum: &[u32]) -> bool {
+        self.java
+            && self
+                .version
+                .as_deref()
+                .and_then(version_parts)
+                .map(|parts| parts.as_slice() >= minimum)
+                .unwrap_or(false)
+    }
+}
+
+fn parse_version(output: &str) -> Option<PdftkVersion> {
+    let description = output.lines().map(str::trim).find(|l| !l.is_empty())?;
+    if !description.to_lowercase().contains("pdftk") {
+        return None;
+    }
+    Some(PdftkVersion {
+        description: description.to_string(),
+        version: description
+            .split_whitespace()
+            .find(|word| version_parts(word).is_some())
+            .map(|word| word.to_string()),
+        java: description.to_lowercase().contains("java"),
+    })
+}
+
+fn run(pdftk_path: &str, args: &[&str]) -> Result<Output> {
+    Command::new(pdftk_path)
+        .args(args)
+        .output()
+        .map_err(|err| pdftk_error(PdftkErrorKind::NotFound(pdftk_path.to_string(), err)))
+}
+
+/// Detects version of pdftk found at the given path
+pub fn version(pdftk_path: &str) -> Result<PdftkVersion> {
+    let output = run(pdftk_path, &["--version"])?;
+    parse_version(String::from_utf8_lossy(&output.stdout).as_ref()).ok_or_else(|| {
+        pdftk_error(PdftkErrorKind::NotPdftk(
+            pdftk_path.to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    })
+}
+
+/// Fills `source_pdf` with values from `xfdf_file` and writes it to `output`, using
+/// `replacement_font` for text fields if it is passed.
+pub fn fill_form(
+    pdftk_path: &str,
+    source_pdf: &str,
+    xfdf_file: &str,
+    output: &str,
+    replacement_font: Option<&str>,
+) -> Result<()> {
+    let version = version(pdftk_path)?;
+    if replacement_font.is_some() && !version.java_at_least(&REPLACEMENT_FONT_VERSION) {
+        return Err(pdftk_error(PdftkErrorKind::ReplacementFontUnsupported(
+            version.to_string(),
+        )));
+    }
+    // Output is written to a temporary file first, so failed runs don't leave partial output
+    // and an older output file can't be mistaken for a new one
+    let temp_output = TempFile::new("pdf")?;
+    let pdftk_output = if output == STDOUT_FILE {
+        STDOUT_FILE
+    } else {
+        temp_output.path_str()?
+    };
+    let mut args = vec![source_pdf, "fill_form", xfdf_file, "output", pdftk_output];
+    if let Some(font) = replacement_font {
+        args.extend_from_slice(&["replacement_font", font]);
+    }
+    let result = run(pdftk_path, &args)?;
+    let stderr = String::from_utf8_lossy(&result.stderr).trim().to_string();
+    if !result.status.success() {
+        return Err(pdftk_error(PdftkErrorKind::Failed {
+            version: version.to_string(),
+            status: result.status.code(),
+            stderr,
+        }));
+    }
+    // Some versions exit successfully even when nothing was written
+    let written = if output == STDOUT_FILE {
+        !result.stdout.is_empty()
+    } else {
+        fs::metadata(&temp_output.0).map(|m| m.len()).unwrap_or(0) > 0
+    };
+    if !written {
+        return Err(pdftk_error(PdftkErrorKind::NoOutput(
+            version.to_string(),
+            stderr,
+        )));
+    }
+    if output == STDOUT_FILE {
+        io::stdout().write_all(&result.stdout)?;
+    } else {
+        fs::copy(&temp_output.0, output)?;
+    }
+    if !stderr.is_empty() {
+        eprintln!("pdftk: {}", stderr);
+    }
+    Ok(())
+}
+
+/// Number of names tried before giving up on creating a temporary file
+static TEMP_FILE_ATTEMPTS: usize = 100;
+
+/// Temporary file, unique for each run and each call. It is removed once dropped, even when
+/// filling the form failed.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    /// Creates a new empty file, readable only by the current user. Existing files are never
+    /// reused, so other users can't prepare a file (or a link) with the same name in advance.
+    pub fn new(extension: &str) -> Result<TempFile> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        for _ in 0..TEMP_FILE_ATTEMPTS {
+            let path = std::env::temp_dir().join(format!(
+                "fbihtax-{}-{}.{}",
+                std::process::id(),
+                TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst),
+                extension
+            ));
+            match options.open(&path) {
+                Ok(_) => return Ok(TempFile(path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(Error::UnexpectedCondition(
+            "Can't create temporary file".to_string(),
+        ))
+    }
+
+    pub fn path_str(&self) -> Result<&str> {
+        self.0
+            .to_str()
+            .ok_or_else(|| Error::UnexpectedCondition("Can't create temporary file".to_string()))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if Path::new(&self.0).exists() {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_version_test() {
+        assert_eq!(
+            Some(PdftkVersion {
+                description: "pdftk port to java 3.3.2 a Handy Tool for Manipulating PDF Documents"
+                    .to_string(),
+                version: Some("3.3.2".to_string()),
+                java: true,
+            }),
+            parse_version(
+                "pdftk port to java 3.3.2 a Handy Tool for Manipulating PDF Documents\nCopyright (c) 2017-2018 Marc Vinyals\n"
+            )
+        );
+        let java = parse_version("pdftk port to java 3.3.2 a Handy Tool").unwrap();
+        assert!(java.java_at_least(&[3, 3, 0]));
+        assert!(java.java_at_least(&[3, 3, 2]));
+        assert!(!java.java_at_least(&[3, 4]));
+        let original =
+            parse_version("\npdftk 2.02 a Handy Tool for Manipulating PDF Documents\n").unwrap();
+        assert_eq!(Some("2.02".to_string()), original.version);
+        assert!(!original.java);
+        assert_eq!("pdftk 2.02", original.to_string());
+        assert!(!original.java_at_least(&[2]));
+        assert_eq!(None, parse_version("qpdf version 10.6.3"));
+        assert_eq!(None, parse_version(""));
+    }
+
+    #[test]
+    fn missing_pdftk_test() {
+        let error = fill_form(
+            "/nonexistent/fbihtax/pdftk",
+            "source.pdf",
+            "values.xfdf",
+            "output.pdf",
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Pdf(PdfErrorKind::Pdftk(PdftkErrorKind::NotFound(..)))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_pdftk_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = TempFile::new("sh").unwrap();
+        fs::write(
+            &script.0,
+            "#!/bin/sh\nif [ \"$1\" = \"--version\" ]; then echo 'pdftk port to java 3.3.2 a Handy Tool'; exit 0; fi\necho 'Error: Unable to find file.' >&2\nexit 3\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script.0, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = script.0.with_extension("pdf");
+        let error = fill_form(
+            script.path_str().unwrap(),
+            "source.pdf",
+            "values.xfdf",
+            output.to_str().unwrap(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "pdftk-java 3.3.2 failed with exit status 3: Error: Unable to find file.",
+            error.to_string()
+        );
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn temp_file_test() {
+        let first = TempFile::new("xfdf").unwrap();
+        let second = TempFile::new("xfdf").unwrap();
+        assert_ne!(first.0, second.0);
+        assert!(first.0.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&first.0).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        // Names of existing files are skipped
+        let taken = std::env::temp_dir().join(format!(
+            "fbihtax-{}-{}.xfdf",
+            std::process::id(),
+            TEMP_FILE_COUNTER.load(Ordering::SeqCst)
+        ));
+        fs::write(&taken, "taken").unwrap();
+        let third = TempFile::new("xfdf").unwrap();
+        assert_ne!(taken, third.0);
+        assert_eq!("taken", fs::read_to_string(&taken).unwrap());
+        fs::remove_file(&taken).unwrap();
+
+        fs::write(&first.0, "test").unwrap();
+        let path = first.0.clone();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
@@ -1,28 +1,75 @@
 use std::{
     collections::HashMap,
-    env::temp_dir,
     fs::File,
     io::{self, Write},
 };
 
+use pdf_forms::{FieldState, Form};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
     config::Config,
-    error::{Error, Result, UserErrorKind},
+    error::{Error, Result},
     fdf::fdf_generator::{self, FdfData},
+    forms::{formutils, pdffont::ReplacementFont, FilledForm},
 };
 
+use super::pdftk::{self, TempFile};
+
 use super::utils::fill_template;
 
+/// Passing this as output file writes output to stdout instead
+pub static STDOUT_FILE: &str = "-";
+
 pub trait Printer {
     fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()>;
+
+    /// Writes filled form. By default, only values of its fields are written
+    fn write_form_to_file(&self, form: &mut dyn FilledForm, file: &str) -> Result<()> {
+        self.write_to_file(form.to_dict()?, file)
+    }
+}
+
+pub trait ListPrinter {
+    fn write_list_to_file(
+        &self,
+        rows: Vec<HashMap<String, String>>,
+        totals: Option<HashMap<String, String>>,
+        file: &str,
+    ) -> Result<()>;
+}
+
+/// Tool used to produce filled PDF forms
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfBackend {
+    /// Filled form is saved directly
+    Native,
+    /// Form is filled by external pdftk from XFDF
+    Pdftk,
+}
+
+impl std::fmt::Display for PdfBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match &self {
+            PdfBackend::Native => "native",
+            PdfBackend::Pdftk => "pdftk",
+        })
+    }
 }
 
-pub struct PdfPrinter<'a> {
+pub struct NativePdfPrinter {
+    pub source_pdf: String,
+    /// Path to TTF file used to draw text fields
+    pub font: Option<String>,
+}
+pub struct PdftkPrinter<'a> {
     pub config: &'a Config,
     pub source_pdf: String,
     pub xfdf_printer: &'a XfdfPrinter,
+    /// Font passed to pdftk as `replacement_font`
+    pub font: Option<String>,
 }
 pub struct FdfPrinter {}
 pub struct XfdfPrinter {}
@@ -32,6 +79,35 @@ pub struct JsonPrinter {
 pub struct StdoutPrinter {
     pub output_template: String,
 }
+pub struct TablePrinter {
+    pub columns: Vec<String>,
+}
+pub struct CsvPrinter {
+    pub columns: Vec<String>,
+}
+
+fn create_writer(file: &str) -> Result<Box<dyn Write>> {
+    if file == STDOUT_FILE {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(File::create(file)?))
+    }
+}
+
+/// Known columns in their order, followed by any other keys present in data
+fn ordered_columns(columns: &[String], data: &[&HashMap<String, String>]) -> Vec<String> {
+    let mut result = columns.to_vec();
+    let mut extra: Vec<String> = data
+        .iter()
+        .flat_map(|row| row.keys())
+        .filter(|key| !result.contains(key))
+        .cloned()
+        .collect();
+    extra.sort();
+    extra.dedup();
+    result.append(&mut extra);
+    result
+}
 
 fn default_json_formatter(data: HashMap<String, String>) -> Result<serde_json::Value> {
     Ok(json!(data))
@@ -39,30 +115,58 @@ fn default_json_formatter(data: HashMap<String, String>) -> Result<serde_json::V
 
 impl Default for JsonPrinter {
     fn default() -> Self {
-        return Self {
+        Self {
             json_formatter: Box::new(default_json_formatter),
-        };
+        }
     }
 }
-impl<'a> Printer for PdfPrinter<'a> {
+
+impl NativePdfPrinter {
+    fn write_pdf_form(&self, pdf_form: &Form, file: &str) -> Result<()> {
+        // Font is loaded first, so output is not touched when it can't be used
+        let font = self
+            .font
+            .as_deref()
+            .map(ReplacementFont::load)
+            .transpose()?;
+        let mut writer = create_writer(file)?;
+        formutils::write_pdf_form(pdf_form, font.as_ref(), &mut writer)
+    }
+}
+
+impl Printer for NativePdfPrinter {
+    /// Fills text fields of the source form by their names
     fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()> {
-        let mut tmp_fdf_file = temp_dir();
-        tmp_fdf_file.push("fbihtax.xfdf");
-        let tmp_fdf_file_str = tmp_fdf_file.to_str().ok_or(Error::UnexpectedCondition(
-            "Can't create temporary file".to_string(),
-        ))?;
-        self.xfdf_printer.write_to_file(data, tmp_fdf_file_str)?;
-        let _process = std::process::Command::new(&self.config.pdf.pdftk_path)
-            .args(&[
-                self.source_pdf.clone(),
-                "fill_form".to_string(),
-                tmp_fdf_file_str.to_string(),
-                "output".to_string(),
-                file.to_string()
-            ])
-            .output()
-            .map_err(|_| Error::UserError(UserErrorKind::Generic("Failed to execute pdftk. Ensure it is installed and path is properly configured in .fbihtax.json".to_string())));
-        Ok(())
+        let mut pdf_form = Form::load(self.source_pdf.as_str())?;
+        for index in 0..pdf_form.len() {
+            let value = match pdf_form.get_name(index).and_then(|name| data.get(&name)) {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            if let FieldState::Text { .. } = pdf_form.get_state(index) {
+                formutils::fill_field(&mut pdf_form, index, value)?;
+            }
+        }
+        self.write_pdf_form(&pdf_form, file)
+    }
+
+    fn write_form_to_file(&self, form: &mut dyn FilledForm, file: &str) -> Result<()> {
+        self.write_pdf_form(form.pdf_form()?, file)
+    }
+}
+
+impl<'a> Printer for PdftkPrinter<'a> {
+    fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()> {
+        let xfdf_file = TempFile::new("xfdf")?;
+        self.xfdf_printer
+            .write_to_file(data, xfdf_file.path_str()?)?;
+        pdftk::fill_form(
+            self.config.pdf.pdftk_path.as_str(),
+            self.source_pdf.as_str(),
+            xfdf_file.path_str()?,
+            file,
+            self.font.as_deref(),
+        )
     }
 }
 
@@ -82,17 +186,145 @@ impl Printer for XfdfPrinter {
 
 impl Printer for JsonPrinter {
     fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()> {
-        let breakdown_writer = File::create(file)?;
+        let breakdown_writer = create_writer(file)?;
         let result_json = (self.json_formatter)(data)?;
         serde_json::to_writer_pretty(breakdown_writer, &result_json)?;
         Ok(())
     }
 }
 
+impl ListPrinter for JsonPrinter {
+    fn write_list_to_file(
+        &self,
+        rows: Vec<HashMap<String, String>>,
+        totals: Option<HashMap<String, String>>,
+        file: &str,
+    ) -> Result<()> {
+        let writer = create_writer(file)?;
+        let entries = rows
+            .into_iter()
+            .map(|row| (self.json_formatter)(row))
+            .collect::<Result<Vec<serde_json::Value>>>()?;
+        let result_json = match totals {
+            Some(totals) => json!({ "entries": entries, "totals": totals }),
+            None => json!({ "entries": entries }),
+        };
+        serde_json::to_writer_pretty(writer, &result_json)?;
+        Ok(())
+    }
+}
+
+impl Printer for TablePrinter {
+    fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()> {
+        let mut writer = create_writer(file)?;
+        let columns = ordered_columns(&self.columns, &[&data]);
+        let width = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+        for column in columns {
+            if let Some(value) = data.get(&column) {
+                let line = format!("{:<width$}  {}", column, value, width = width);
+                writeln!(writer, "{}", line.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ListPrinter for TablePrinter {
+    fn write_list_to_file(
+        &self,
+        rows: Vec<HashMap<String, String>>,
+        totals: Option<HashMap<String, String>>,
+        file: &str,
+    ) -> Result<()> {
+        let mut writer = create_writer(file)?;
+        let mut all_rows: Vec<&HashMap<String, String>> = rows.iter().collect();
+        if let Some(totals) = &totals {
+            all_rows.push(totals);
+        }
+        let columns = ordered_columns(&self.columns, &all_rows);
+        let widths: Vec<usize> = columns
+            .iter()
+            .map(|column| {
+                all_rows
+                    .iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|value| value.chars().count())
+                    .max()
+                    .unwrap_or(0)
+                    .max(column.len())
+            })
+            .collect();
+        let format_row = |row: &HashMap<String, String>| -> String {
+            columns
+                .iter()
+                .zip(widths.iter())
+                .map(|(column, width)| {
+                    format!(
+                        "{:<width$}",
+                        row.get(column).map(String::as_str).unwrap_or(""),
+                        width = width
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        let header: HashMap<String, String> =
+            columns.iter().map(|c| (c.clone(), c.clone())).collect();
+        let separator: String =
+            "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len().max(1) - 1));
+        writeln!(writer, "{}", format_row(&header))?;
+        writeln!(writer, "{}", separator)?;
+        for row in &rows {
+            writeln!(writer, "{}", format_row(row))?;
+        }
+        if let Some(totals) = &totals {
+            writeln!(writer, "{}", separator)?;
+            writeln!(writer, "{}", format_row(totals))?;
+        }
+        Ok(())
+    }
+}
+
+impl Printer for CsvPrinter {
+    fn write_to_file(&self, data: HashMap<String, String>, file: &str) -> Result<()> {
+        self.write_list_to_file(vec![data], None, file)
+    }
+}
+
+impl ListPrinter for CsvPrinter {
+    fn write_list_to_file(
+        &self,
+        rows: Vec<HashMap<String, String>>,
+        totals: Option<HashMap<String, String>>,
+        file: &str,
+    ) -> Result<()> {
+        let mut all_rows: Vec<&HashMap<String, String>> = rows.iter().collect();
+        if let Some(totals) = &totals {
+            all_rows.push(totals);
+        }
+        let columns = ordered_columns(&self.columns, &all_rows);
+        let mut writer = csv::Writer::from_writer(create_writer(file)?);
+        writer.write_record(&columns).map_err(Error::from)?;
+        for row in all_rows {
+            writer
+                .write_record(
+                    columns
+                        .iter()
+                        .map(|column| row.get(column).map(String::as_str).unwrap_or("")),
+                )
+                .map_err(Error::from)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
 impl Printer for StdoutPrinter {
     fn write_to_file(&self, data: HashMap<String, String>, _file: &str) -> Result<()> {
         let result = fill_template(self.output_template.clone(), data);
-        io::stdout().write(result.as_bytes())?;
+        io::stdout().write_all(result.as_bytes())?;
         Ok(())
     }
 }
@@ -5,5 +5,5 @@ pub fn fill_template(template: String, data: HashMap<String, String>) -> String
     for (key, value) in data {
         result = result.replace(format!("{{{}}}", key).as_str(), value.as_str());
     }
-    return result;
+    result
 }
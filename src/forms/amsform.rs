@@ -3,9 +3,13 @@ extern crate rust_decimal;
 use std::{collections::HashMap, ops::Add};
 
 use crate::{
+    dates,
     db::AmsInfo,
-    error::{Error, Result},
-    forms::formutils::{fill_field, format_money_value},
+    error::{Error, Result, UserErrorKind},
+    forms::{
+        formutils::{fill_field, format_money_value, text_value},
+        FilledForm,
+    },
     taxcalculator,
 };
 use pdf_forms::Form;
@@ -31,7 +35,6 @@ pub enum FormField {
     TaxAmountTotal = 45,
     TaxPairAbroadTotal = 46,
     TaxToPayTotal = 47,
-    Date = 48,
 }
 
 #[derive(Clone, Copy)]
@@ -45,9 +48,9 @@ enum RepeatingFormField {
 }
 
 static REPEATING_FIELDS_START: u32 = 13;
-static REPEATED_LINES: u32 = 5;
 static REPEATED_FIELDS_COUNT: u32 = 6;
 
+#[derive(Clone, Copy)]
 struct IncomeLine {
     value: Decimal,
     health_insurance: Decimal,
@@ -72,24 +75,6 @@ impl Add for IncomeLine {
     }
 }
 
-impl Clone for IncomeLine {
-    fn clone_from(&mut self, source: &Self) {
-        *self = source.clone()
-    }
-
-    fn clone(&self) -> Self {
-        Self {
-            value: self.value,
-            health_insurance: self.health_insurance,
-            tax_base: self.tax_base,
-            tax_amount: self.tax_amount,
-            tax_paid_abroad: self.tax_paid_abroad,
-            tax_to_pay: self.tax_to_pay,
-        }
-    }
-}
-impl Copy for IncomeLine {}
-
 pub struct AmsForm {
     pdf_form: Form,
     fields: HashMap<usize, String>,
@@ -142,10 +127,12 @@ impl AmsForm {
             tax_to_pay,
         };
         self.income_lines.push(income_line);
-        return AmsInfo {
+        AmsInfo {
             income_total: base_value,
+            health_insurance: Some(health_insurance),
             tax_paid: tax_to_pay,
-        };
+            ..Default::default()
+        }
     }
 
     fn fill_income_lines(&mut self) -> Result<()> {
@@ -158,8 +145,8 @@ impl AmsForm {
             .copied()
             .reduce(|acc, x| acc + x)
             .unwrap();
-        let mut counter = 0;
-        for income_line in self.income_lines.clone() {
+        for (counter, income_line) in self.income_lines.clone().into_iter().enumerate() {
+            let counter = counter as u32;
             self.fill_repeating_field(
                 counter,
                 RepeatingFormField::IncomeValue,
@@ -190,7 +177,6 @@ impl AmsForm {
                 RepeatingFormField::TaxToPay,
                 format_money_value(income_line.tax_to_pay),
             )?;
-            counter += 1;
         }
         self.fill_main_field(
             FormField::HealthInsuranceTotal,
@@ -211,52 +197,103 @@ impl AmsForm {
         )
     }
 
+    /// Name of the field in the PDF document, as used in FDF, XFDF and JSON outputs
+    fn field_name(&self, index: usize) -> Option<String> {
+        match self.pdf_form.get_name(index) {
+            Some(name) => Some(name),
+            // pdf_forms has a bug when loading names with non ascii characters
+            // this patches one such occurence in the document
+            None if index == FormField::CompanyCountry as usize => {
+                Some("8 Dr&#382;ava".to_string())
+            }
+            None => None,
+        }
+    }
+
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_dict(&mut self) -> Result<HashMap<String, String>> {
         self.fill_income_lines()?;
         Ok(self
             .fields
             .iter()
-            .map(|(k, v)| match self.pdf_form.get_name(k.clone()) {
-                Some(name) => (name, v.clone()),
-                None => {
-                    // pdf_forms has a bug when loading names with non ascii characters
-                    // this patches one such occurence in the document
-                    if k.clone() == FormField::CompanyCountry as usize {
-                        ("8 Dr&#382;ava".to_string(), v.clone())
-                    } else {
-                        ("".to_string(), "".to_string())
-                    }
-                }
-            })
-            .filter(|(k, _)| !k.is_empty())
+            .filter_map(|(k, v)| self.field_name(*k).map(|name| (name, v.clone())))
             .collect())
     }
 
+    /// Fills text fields from values keyed by field names, as produced by `to_dict`.
+    /// Returns the number of fields which were found in the form.
+    pub fn fill_from_dict(&mut self, dict: &HashMap<String, String>) -> Result<usize> {
+        let mut filled = 0;
+        for index in 0..self.pdf_form.len() {
+            let value = match self.field_name(index).and_then(|name| dict.get(&name)) {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            if let pdf_forms::FieldState::Text { .. } = self.pdf_form.get_state(index) {
+                self.fields.insert(index, value.clone());
+                fill_field(&mut self.pdf_form, index, value)?;
+                filled += 1;
+            }
+        }
+        Ok(filled)
+    }
+
     pub fn get_number_field_value(&self, field: FormField) -> Result<Decimal> {
         Decimal::from_str_radix(self.get_text_field_value(field)?.as_str(), 10)
             .map_err(|err| Error::UnexpectedCondition(err.to_string()))
     }
 
     pub fn get_text_field_value(&self, field: FormField) -> Result<String> {
-        println!(
-            "Loading text field {} of {}",
-            field as usize,
-            self.pdf_form.len()
-        );
-        match self.pdf_form.get_state(field as usize) {
-            pdf_forms::FieldState::Text {
-                text,
-                readonly,
-                required,
-            } => {
-                println!("Loaded text: {}", text);
-                Ok(text)
+        match self.pdf_form.get_type(field as usize) {
+            pdf_forms::FieldType::Text => {
+                Ok(text_value(&self.pdf_form, field as usize).unwrap_or_default())
             }
             _ => Err(Error::UnexpectedCondition(
                 "Unsupported field type!".to_string(),
             )),
         }
     }
+
+    /// Payment date in YYYY-MM-DD format
+    pub fn get_payment_date(&self) -> Result<String> {
+        payment_date(
+            self.get_text_field_value(FormField::PaymentDateYear)?
+                .as_str(),
+            self.get_text_field_value(FormField::PaymentDateMonth)?
+                .as_str(),
+            self.get_text_field_value(FormField::PaymentDateDay)?
+                .as_str(),
+        )
+    }
+}
+
+/// Builds date from payment date fields. Form only has room for the last 2 digits of the year.
+fn payment_date(year: &str, month: &str, day: &str) -> Result<String> {
+    let (year, month, day) = (year.trim(), month.trim(), day.trim());
+    if year.is_empty() || month.is_empty() || day.is_empty() {
+        return Err(Error::UserError(UserErrorKind::Generic(
+            "Payment date is missing".to_string(),
+        )));
+    }
+    let year = if year.len() == 2 {
+        format!("20{}", year)
+    } else {
+        year.to_string()
+    };
+    let date = format!("{}-{:0>2}-{:0>2}", year, month, day);
+    dates::parse_date(date.as_str())?;
+    Ok(date)
+}
+
+impl FilledForm for AmsForm {
+    fn to_dict(&mut self) -> Result<HashMap<String, String>> {
+        AmsForm::to_dict(self)
+    }
+
+    fn pdf_form(&mut self) -> Result<&mut Form> {
+        self.fill_income_lines()?;
+        Ok(&mut self.pdf_form)
+    }
 }
 
 pub fn load_ams_form(input_file: String) -> Result<AmsForm> {
@@ -291,4 +328,12 @@ mod tests {
             assert_eq!(expected, format_money_value(value))
         }
     }
+
+    #[test]
+    fn payment_date_test() {
+        assert_eq!("2021-03-10", payment_date("21", "03", "10").unwrap());
+        assert_eq!("2021-03-05", payment_date("2021", "3", "5").unwrap());
+        assert!(payment_date("21", "13", "10").is_err());
+        assert!(payment_date("", "03", "10").is_err());
+    }
 }
@@ -1,8 +1,13 @@
-use pdf_forms::Form;
+use std::io::Write;
+
+use lopdf::{Dictionary, Document, Object, StringFormat};
+use pdf_forms::{FieldType, Form};
 use rust_decimal::Decimal;
 
 use crate::error::{Error, Result};
 
+use super::pdffont::{self, ReplacementFont};
+
 pub fn format_money_value(value: Decimal) -> String {
     value.round_dp(2).to_string()
 }
@@ -11,12 +16,183 @@ pub fn fill_field(pdf_form: &mut Form, field_index: usize, value: String) -> Res
     pdf_form.set_text(field_index, value).map_err(Error::from)
 }
 
+/// PDF text strings are either PDFDocEncoding or UTF-16BE with a byte order mark, while
+/// pdf_forms stores values as UTF-8. Values with non ASCII characters are converted to UTF-16.
+fn encode_text_value(value: &[u8]) -> Option<Vec<u8>> {
+    if value.is_ascii() || value.starts_with(&[0xfe, 0xff]) {
+        return None;
+    }
+    let text = std::str::from_utf8(value).ok()?;
+    let mut encoded = vec![0xfe, 0xff];
+    encoded.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+    Some(encoded)
+}
+
+/// Value of the text field at `index`, decoding UTF-16 values written by this module or
+/// other tools. pdf_forms only reads UTF-8 values and panics on others.
+pub fn text_value(pdf_form: &Form, index: usize) -> Option<String> {
+    let field = pdf_form
+        .document
+        .get_dictionary(pdf_form.get_object_id(index))
+        .ok()?;
+    let value = match field.get(b"V") {
+        Ok(Object::String(value, _)) => value,
+        _ => return Some(String::new()),
+    };
+    if let Some(utf16) = value.strip_prefix(&[0xfe, 0xff]) {
+        let units: Vec<u16> = utf16
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect();
+        return Some(String::from_utf16_lossy(&units));
+    }
+    Some(String::from_utf8_lossy(value).to_string())
+}
+
+/// Interactive form dictionary of the document, either referenced or inline in the catalog
+pub fn acroform_mut(document: &mut Document) -> Result<&mut Dictionary> {
+    let root_id = document.trailer.get(b"Root")?.as_reference()?;
+    match document.get_dictionary(root_id)?.get(b"AcroForm")? {
+        Object::Reference(id) => {
+            let id = *id;
+            Ok(document.get_object_mut(id)?.as_dict_mut()?)
+        }
+        _ => Ok(document
+            .get_object_mut(root_id)?
+            .as_dict_mut()?
+            .get_mut(b"AcroForm")?
+            .as_dict_mut()?),
+    }
+}
+
+/// Writes filled PDF form, drawing its text fields with the replacement font if there is one.
+/// Values are re-encoded in a copy of the document, so the form itself can still be read
+/// afterwards.
+pub fn write_pdf_form<W: Write>(
+    pdf_form: &Form,
+    font: Option<&ReplacementFont>,
+    writer: &mut W,
+) -> Result<()> {
+    let mut document = pdf_form.document.clone();
+    let mut reencoded = false;
+    for index in 0..pdf_form.len() {
+        if let FieldType::Text = pdf_form.get_type(index) {
+            let field = document
+                .get_object_mut(pdf_form.get_object_id(index))?
+                .as_dict_mut()?;
+            let encoded = match field.get(b"V") {
+                Ok(Object::String(value, _)) => encode_text_value(value),
+                _ => None,
+            };
+            if let Some(encoded) = encoded {
+                field.set("V", Object::String(encoded, StringFormat::Hexadecimal));
+                reencoded = true;
+            }
+        }
+    }
+    match font {
+        Some(font) => {
+            let missing = pdffont::apply_font(&mut document, pdf_form, font)?;
+            if !missing.is_empty() {
+                eprintln!(
+                    "Warning: replacement font has no glyphs for characters: {}. They are drawn as empty boxes",
+                    missing.iter().map(|c| format!("'{}'", c)).collect::<Vec<String>>().join(", ")
+                );
+            }
+        }
+        // Appearances generated by pdf_forms can only show ASCII text, so viewers are asked
+        // to generate them again
+        None if reencoded => acroform_mut(&mut document)?.set("NeedAppearances", true),
+        None => {}
+    }
+    document.save_to(writer)?;
+    Ok(())
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use lopdf::{dictionary, Dictionary};
     use rust_decimal_macros::dec;
 
     use super::*;
 
+    /// Minimal PDF with a form containing text fields with given names
+    pub fn test_form(field_names: &[&str]) -> Form {
+        let mut document = Document::with_version("1.5");
+        let fields: Vec<Object> = field_names
+            .iter()
+            .map(|name| {
+                document
+                    .add_object(dictionary! {
+                        "FT" => "Tx",
+                        "T" => Object::string_literal(*name),
+                        "DA" => Object::string_literal("/Helv 10 Tf 0 g"),
+                        "Rect" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+                    })
+                    .into()
+            })
+            .collect();
+        let acroform_id = document.add_object(dictionary! { "Fields" => fields });
+        let pages_id = document.add_object(dictionary! {
+            "Type" => "Pages",
+            "Kids" => Vec::<Object>::new(),
+            "Count" => 0,
+        });
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "AcroForm" => acroform_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        Form::load_from(bytes.as_slice()).unwrap()
+    }
+
+    fn field_value(document: &Document, pdf_form: &Form, index: usize) -> Vec<u8> {
+        document
+            .get_dictionary(pdf_form.get_object_id(index))
+            .and_then(|field: &Dictionary| field.get(b"V"))
+            .and_then(|value| value.as_str())
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn write_pdf_form_test() {
+        let mut pdf_form = test_form(&["Name", "Country"]);
+        fill_field(&mut pdf_form, 0, "Ensar".to_string()).unwrap();
+        fill_field(&mut pdf_form, 1, "Država".to_string()).unwrap();
+
+        let mut bytes = Vec::new();
+        write_pdf_form(&pdf_form, None, &mut bytes).unwrap();
+        let saved = Document::load_mem(&bytes).unwrap();
+
+        assert_eq!(b"Ensar".to_vec(), field_value(&saved, &pdf_form, 0));
+        let country: Vec<u8> = [0xfe, 0xff]
+            .into_iter()
+            .chain("Država".encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+            .collect();
+        assert_eq!(country, field_value(&saved, &pdf_form, 1));
+        let acroform_id = saved.catalog().unwrap().get(b"AcroForm").unwrap();
+        let acroform = saved
+            .get_dictionary(acroform_id.as_reference().unwrap())
+            .unwrap();
+        assert!(matches!(
+            acroform.get(b"NeedAppearances"),
+            Ok(Object::Boolean(true))
+        ));
+        // Filled form is left as it was
+        assert_eq!(
+            "Država".as_bytes().to_vec(),
+            field_value(&pdf_form.document, &pdf_form, 1)
+        );
+
+        let saved_form = Form::load_from(bytes.as_slice()).unwrap();
+        assert_eq!(Some("Ensar".to_string()), text_value(&saved_form, 0));
+        assert_eq!(Some("Država".to_string()), text_value(&saved_form, 1));
+    }
+
     #[test]
     fn format_money_value_test() {
         let pairs = [
@@ -2,11 +2,17 @@ extern crate pdf_forms;
 extern crate rust_decimal;
 use std::{collections::HashMap, ops::Add};
 
+use chrono::NaiveDate;
 use pdf_forms::Form;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 
-use crate::{config::UserConfig, error::Result};
+use crate::{
+    config::UserConfig,
+    db::{GpdIncomeInfo, GpdInfo},
+    error::Result,
+    forms::FilledForm,
+};
 
 use super::formutils::{fill_field, format_money_value};
 
@@ -19,15 +25,12 @@ pub enum FormField {
     UserPhone = 4,
     UserEmail = 5,
     GipIncome = 6,
-    SprExpense = 7,
-    SprIncome = 8,
     AgroExpense = 9,
     AgroIncome = 10,
     RentExpense = 11,
     RentIncome = 12,
     Rent2Expense = 13,
     Rent2Income = 14,
-    AugExpense = 15,
     AugIncome = 16,
     PreviousExpense = 17,
     ExpenseSum = 18,
@@ -46,14 +49,8 @@ pub enum FormField {
     TotalDeductionP2 = 31,
     TaxBaseP2 = 32,
     TaxTotalP2 = 33,
-    TaxDeductionF27P2 = 34,
-    F29P2 = 35,
     TaxPaidP2 = 36,
-    ExternalPaidTaxP2 = 37,
-    RequestReturnP2 = 38,
     ReturnTotalP2 = 39,
-    AccountNumberP2 = 40,
-    DateP2 = 41,
     PeriodStart = 42,
     PeriodEnd = 43,
 }
@@ -63,11 +60,27 @@ pub struct GpdForm {
     fields: HashMap<usize, String>,
     gip_info: Option<TaxInfo>,
     ams_info: Option<TaxInfo>,
+    agro_info: Option<TaxInfo>,
+    rent_info: Option<TaxInfo>,
+    rent2_info: Option<TaxInfo>,
+    previous_loss: Decimal,
     deductions: Deductions,
 }
 
+struct GpdTotals {
+    income: Decimal,
+    expense: Decimal,
+    previous_loss_used: Decimal,
+    income_total: Decimal,
+    loss: Decimal,
+    tax_base: Decimal,
+    tax_to_pay: Decimal,
+    tax_paid: Decimal,
+}
+
 struct TaxInfo {
     income: Decimal,
+    expense: Decimal,
     tax_paid: Decimal,
 }
 
@@ -83,15 +96,27 @@ impl Add for TaxInfo {
     fn add(self, rhs: Self) -> Self::Output {
         Self {
             income: self.income + rhs.income,
+            expense: self.expense + rhs.expense,
             tax_paid: self.tax_paid + rhs.tax_paid,
         }
     }
 }
 
+impl TaxInfo {
+    fn to_gpd_income_info(&self) -> GpdIncomeInfo {
+        GpdIncomeInfo {
+            income: self.income,
+            expense: self.expense,
+            tax_paid: self.tax_paid,
+        }
+    }
+}
+
 impl Clone for TaxInfo {
     fn clone(&self) -> Self {
         Self {
             income: self.income,
+            expense: self.expense,
             tax_paid: self.tax_paid,
         }
     }
@@ -109,7 +134,7 @@ impl Default for Deductions {
 
 impl Deductions {
     fn get_total(&self) -> Decimal {
-        return self.personal + self.health + self.interest;
+        self.personal + self.health + self.interest
     }
 }
 
@@ -135,10 +160,18 @@ impl GpdForm {
         )
     }
 
-    pub fn fill_year_info(&mut self, year: String) -> Result<()> {
+    pub fn fill_year_info(
+        &mut self,
+        year: String,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+    ) -> Result<()> {
         let year_last_2 = &year[2..year.len()];
-        self.fill_field(FormField::PeriodStart, "0101".to_string())?;
-        self.fill_field(FormField::PeriodEnd, "3112".to_string())?;
+        self.fill_field(
+            FormField::PeriodStart,
+            period_start.format("%d%m").to_string(),
+        )?;
+        self.fill_field(FormField::PeriodEnd, period_end.format("%d%m").to_string())?;
         self.fill_field(FormField::TaxYearLast2, year_last_2.to_string())?;
         self.fill_field(FormField::TaxYearLast2P2, year_last_2.to_string())
     }
@@ -146,6 +179,7 @@ impl GpdForm {
     pub fn add_gip_info(&mut self, gip_income: Decimal, gip_tax_paid: Decimal) {
         self.gip_info = Some(TaxInfo {
             income: gip_income,
+            expense: dec!(0),
             tax_paid: gip_tax_paid,
         });
     }
@@ -153,10 +187,112 @@ impl GpdForm {
     pub fn add_ams_info(&mut self, ams_income: Decimal, ams_tax_paid: Decimal) {
         self.ams_info = Some(TaxInfo {
             income: ams_income,
+            expense: dec!(0),
             tax_paid: ams_tax_paid,
         });
     }
 
+    pub fn add_agro_info(&mut self, income: Decimal, expense: Decimal, tax_paid: Decimal) {
+        self.agro_info = Some(TaxInfo {
+            income,
+            expense,
+            tax_paid,
+        });
+    }
+
+    pub fn add_rent_info(&mut self, income: Decimal, expense: Decimal, tax_paid: Decimal) {
+        self.rent_info = Some(TaxInfo {
+            income,
+            expense,
+            tax_paid,
+        });
+    }
+
+    pub fn add_rent2_info(&mut self, income: Decimal, expense: Decimal, tax_paid: Decimal) {
+        self.rent2_info = Some(TaxInfo {
+            income,
+            expense,
+            tax_paid,
+        });
+    }
+
+    /// Unused loss from previous years, which can be deducted from this year's income
+    pub fn add_previous_loss(&mut self, previous_loss: Decimal) {
+        self.previous_loss = previous_loss;
+    }
+
+    fn calculate_totals(&self) -> GpdTotals {
+        let total_tax_info = [
+            &self.gip_info,
+            &self.agro_info,
+            &self.rent_info,
+            &self.rent2_info,
+            &self.ams_info,
+        ]
+        .iter()
+        .filter_map(|tax_info| (*tax_info).clone())
+        .fold(
+            TaxInfo {
+                income: dec!(0),
+                expense: dec!(0),
+                tax_paid: dec!(0),
+            },
+            |acc, x| acc + x,
+        );
+        let current_total = total_tax_info.income - total_tax_info.expense;
+        // Previous losses can only reduce income, never create a new loss
+        let previous_loss_used = self.previous_loss.min(current_total.max(dec!(0)));
+        let income_total = (current_total - previous_loss_used).max(dec!(0));
+        let tax_base = (income_total - self.deductions.get_total()).max(dec!(0));
+        GpdTotals {
+            income: total_tax_info.income,
+            expense: total_tax_info.expense + previous_loss_used,
+            previous_loss_used,
+            income_total,
+            loss: (-current_total).max(dec!(0)),
+            tax_base,
+            tax_to_pay: tax_base * dec!(0.1),
+            tax_paid: total_tax_info.tax_paid,
+        }
+    }
+
+    pub fn get_gpd_info(&self) -> GpdInfo {
+        let totals = self.calculate_totals();
+        GpdInfo {
+            gip: self.gip_info.as_ref().map(TaxInfo::to_gpd_income_info),
+            agro: self.agro_info.as_ref().map(TaxInfo::to_gpd_income_info),
+            rent: self.rent_info.as_ref().map(TaxInfo::to_gpd_income_info),
+            rent2: self.rent2_info.as_ref().map(TaxInfo::to_gpd_income_info),
+            ams: self.ams_info.as_ref().map(TaxInfo::to_gpd_income_info),
+            personal_deduction: self.deductions.personal,
+            health_deduction: self.deductions.health,
+            interest_deduction: self.deductions.interest,
+            previous_loss: self.previous_loss,
+            previous_loss_used: totals.previous_loss_used,
+            loss: totals.loss,
+            income_total: totals.income_total,
+            tax_base: totals.tax_base,
+            tax_amount: totals.tax_to_pay,
+            tax_paid: totals.tax_paid,
+            tax_due: (totals.tax_to_pay - totals.tax_paid).max(dec!(0)),
+            refund: (totals.tax_paid - totals.tax_to_pay).max(dec!(0)),
+            ..Default::default()
+        }
+    }
+
+    fn fill_tax_info_fields(
+        &mut self,
+        tax_info: &TaxInfo,
+        income_field: FormField,
+        expense_field: Option<FormField>,
+    ) -> Result<()> {
+        self.fill_field(income_field, format_money_value(tax_info.income))?;
+        match expense_field {
+            Some(field) => self.fill_field(field, format_money_value(tax_info.expense)),
+            None => Ok(()),
+        }
+    }
+
     pub fn add_deductions(&mut self, personal: Decimal, health: Decimal, interest: Decimal) {
         self.deductions = Deductions {
             personal,
@@ -165,40 +301,47 @@ impl GpdForm {
         };
     }
 
-    pub fn to_dict(&mut self) -> Result<HashMap<String, String>> {
-        let mut total_tax_info = TaxInfo {
-            income: dec!(0),
-            tax_paid: dec!(0),
-        };
-        match &self.gip_info.clone() {
-            Some(gip) => {
-                self.fill_field(FormField::GipIncome, format_money_value(gip.income))?;
-                total_tax_info = total_tax_info + gip.clone();
-            }
-            None => {}
-        }
-        match &self.ams_info.clone() {
-            Some(ams) => {
-                self.fill_field(FormField::AugIncome, format_money_value(ams.income))?;
-                total_tax_info = total_tax_info + ams.clone();
+    fn fill_totals(&mut self) -> Result<()> {
+        let sections = [
+            (self.gip_info.clone(), FormField::GipIncome, None),
+            (
+                self.agro_info.clone(),
+                FormField::AgroIncome,
+                Some(FormField::AgroExpense),
+            ),
+            (
+                self.rent_info.clone(),
+                FormField::RentIncome,
+                Some(FormField::RentExpense),
+            ),
+            (
+                self.rent2_info.clone(),
+                FormField::Rent2Income,
+                Some(FormField::Rent2Expense),
+            ),
+            (self.ams_info.clone(), FormField::AugIncome, None),
+        ];
+        for (tax_info, income_field, expense_field) in sections {
+            if let Some(tax_info) = tax_info {
+                self.fill_tax_info_fields(&tax_info, income_field, expense_field)?;
             }
-            None => {}
         }
+        let totals = self.calculate_totals();
         self.fill_field(
-            FormField::IncomeSum,
-            format_money_value(total_tax_info.income),
+            FormField::PreviousExpense,
+            format_money_value(totals.previous_loss_used),
         )?;
-        // TODO: Add support for expenses?
-        self.fill_field(FormField::ExpenseSum, format_money_value(dec!(0)))?;
-        self.fill_field(FormField::ExpenseTotal, format_money_value(dec!(0)))?;
-        self.fill_field(FormField::ExpenseTotalP2, format_money_value(dec!(0)))?;
+        self.fill_field(FormField::IncomeSum, format_money_value(totals.income))?;
+        self.fill_field(FormField::ExpenseSum, format_money_value(totals.expense))?;
+        self.fill_field(FormField::ExpenseTotal, format_money_value(totals.loss))?;
+        self.fill_field(FormField::ExpenseTotalP2, format_money_value(totals.loss))?;
         self.fill_field(
             FormField::IncomeTotal,
-            format_money_value(total_tax_info.income),
+            format_money_value(totals.income_total),
         )?;
         self.fill_field(
             FormField::IncomeTotalP2,
-            format_money_value(total_tax_info.income),
+            format_money_value(totals.income_total),
         )?;
         self.fill_field(
             FormField::PersonalDeduction,
@@ -220,22 +363,22 @@ impl GpdForm {
             FormField::TotalDeductionP2,
             format_money_value(self.deductions.get_total()),
         )?;
-        let tax_base = total_tax_info.income - dec!(0) - self.deductions.get_total();
-        let tax_to_pay = tax_base * dec!(0.1);
-        self.fill_field(FormField::TaxBaseP2, format_money_value(tax_base))?;
-        self.fill_field(FormField::TaxTotalP2, format_money_value(tax_to_pay))?;
-        self.fill_field(
-            FormField::TaxPaidP2,
-            format_money_value(total_tax_info.tax_paid),
-        )?;
+        self.fill_field(FormField::TaxBaseP2, format_money_value(totals.tax_base))?;
+        self.fill_field(FormField::TaxTotalP2, format_money_value(totals.tax_to_pay))?;
+        self.fill_field(FormField::TaxPaidP2, format_money_value(totals.tax_paid))?;
         self.fill_field(
             FormField::ReturnTotalP2,
-            format_money_value(tax_to_pay - total_tax_info.tax_paid),
-        )?;
+            format_money_value(totals.tax_to_pay - totals.tax_paid),
+        )
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_dict(&mut self) -> Result<HashMap<String, String>> {
+        self.fill_totals()?;
         Ok(self
             .fields
             .iter()
-            .map(|(k, v)| match self.pdf_form.get_name(k.clone()) {
+            .map(|(k, v)| match self.pdf_form.get_name(*k) {
                 Some(name) => (name, v.clone()),
                 None => ("".to_string(), "".to_string()),
             })
@@ -244,15 +387,108 @@ impl GpdForm {
     }
 }
 
-pub fn load_gpd_form(input_file: String) -> Result<GpdForm> {
-    match Form::load(input_file) {
-        Ok(file) => Ok(GpdForm {
-            pdf_form: file,
+impl FilledForm for GpdForm {
+    fn to_dict(&mut self) -> Result<HashMap<String, String>> {
+        GpdForm::to_dict(self)
+    }
+
+    fn pdf_form(&mut self) -> Result<&mut Form> {
+        self.fill_totals()?;
+        Ok(&mut self.pdf_form)
+    }
+}
+
+impl From<Form> for GpdForm {
+    fn from(pdf_form: Form) -> Self {
+        GpdForm {
+            pdf_form,
             fields: HashMap::new(),
             gip_info: None,
             ams_info: None,
+            agro_info: None,
+            rent_info: None,
+            rent2_info: None,
+            previous_loss: dec!(0),
             deductions: Deductions::default(),
-        }),
+        }
+    }
+}
+
+pub fn load_gpd_form(input_file: String) -> Result<GpdForm> {
+    match Form::load(input_file) {
+        Ok(file) => Ok(GpdForm::from(file)),
         Err(err) => Err(err.into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::forms::formutils::tests::test_form;
+
+    use super::*;
+
+    fn gpd_form() -> GpdForm {
+        GpdForm::from(test_form(&[]))
+    }
+
+    #[test]
+    fn calculate_totals_test() {
+        let mut form = gpd_form();
+        form.add_gip_info(dec!(10000), dec!(900));
+        form.add_ams_info(dec!(5000), dec!(400));
+        form.add_deductions(dec!(3600), dec!(0), dec!(0));
+
+        let totals = form.calculate_totals();
+        assert_eq!(dec!(15000), totals.income);
+        assert_eq!(dec!(0), totals.expense);
+        assert_eq!(dec!(15000), totals.income_total);
+        assert_eq!(dec!(0), totals.loss);
+        assert_eq!(dec!(11400), totals.tax_base);
+        assert_eq!(dec!(1140), totals.tax_to_pay);
+        assert_eq!(dec!(1300), totals.tax_paid);
+
+        let gpd_info = form.get_gpd_info();
+        assert_eq!(dec!(0), gpd_info.tax_due);
+        assert_eq!(dec!(160), gpd_info.refund);
+    }
+
+    #[test]
+    fn calculate_totals_loss_test() {
+        let mut form = gpd_form();
+        form.add_agro_info(dec!(1000), dec!(3000), dec!(0));
+        form.add_rent_info(dec!(500), dec!(0), dec!(50));
+        form.add_previous_loss(dec!(200));
+        form.add_deductions(dec!(3600), dec!(0), dec!(0));
+
+        let totals = form.calculate_totals();
+        assert_eq!(dec!(1500), totals.income);
+        assert_eq!(dec!(3000), totals.expense);
+        // Previous losses are not used when there is no income to reduce
+        assert_eq!(dec!(0), totals.previous_loss_used);
+        assert_eq!(dec!(1500), totals.loss);
+        assert_eq!(dec!(0), totals.income_total);
+        assert_eq!(dec!(0), totals.tax_base);
+        assert_eq!(dec!(50), totals.tax_paid);
+    }
+
+    #[test]
+    fn calculate_totals_previous_loss_test() {
+        let mut form = gpd_form();
+        form.add_gip_info(dec!(1000), dec!(0));
+        form.add_rent2_info(dec!(0), dec!(0), dec!(30));
+        form.add_previous_loss(dec!(1500));
+
+        let totals = form.calculate_totals();
+        assert_eq!(dec!(1000), totals.previous_loss_used);
+        assert_eq!(dec!(1000), totals.expense);
+        assert_eq!(dec!(0), totals.income_total);
+        assert_eq!(dec!(0), totals.loss);
+        assert_eq!(dec!(30), totals.tax_paid);
+
+        form.add_previous_loss(dec!(400));
+        let totals = form.calculate_totals();
+        assert_eq!(dec!(400), totals.previous_loss_used);
+        assert_eq!(dec!(600), totals.income_total);
+        assert_eq!(dec!(60), totals.tax_to_pay);
+    }
+}
@@ -1,3 +1,19 @@
+use std::collections::HashMap;
+
+use pdf_forms::Form;
+
+use crate::error::Result;
+
 pub mod amsform;
-mod formutils;
+pub mod formutils;
 pub mod gpdform;
+pub mod pdffont;
+
+/// Form which can be written out by printers, either as field values or as the filled PDF
+pub trait FilledForm {
+    /// Values of filled fields, keyed by field names
+    fn to_dict(&mut self) -> Result<HashMap<String, String>>;
+
+    /// PDF form with all values filled in
+    fn pdf_form(&mut self) -> Result<&mut Form>;
+}
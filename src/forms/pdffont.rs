@@ -0,0 +1,537 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    fs,
+};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use pdf_forms::{FieldType, Form};
+use ttf_parser::{name_id, Face, GlyphId};
+
+use crate::error::{Error, Result, UserErrorKind};
+
+use super::formutils::{acroform_mut, text_value};
+
+/// Name under which the font is added to form resources
+static FONT_RESOURCE_NAME: &str = "FbihtaxFont";
+/// Space between field border and its text
+static PADDING: f64 = 2.0;
+/// Font size used for fields with automatic font size, unless the text doesn't fit
+static MAX_AUTO_FONT_SIZE: f64 = 12.0;
+/// Field flag of comb fields, which show each character in its own cell
+static COMB_FLAG: i64 = 1 << 24;
+
+/// TrueType font embedded into filled forms, used to draw values of their text fields
+pub struct ReplacementFont {
+    data: Vec<u8>,
+}
+
+fn font_error<R: Display>(path: &str, reason: R) -> Error {
+    Error::UserError(UserErrorKind::Generic(format!(
+        "Can't use {} as replacement font: {}",
+        path, reason
+    )))
+}
+
+impl ReplacementFont {
+    pub fn load(path: &str) -> Result<ReplacementFont> {
+        let data = fs::read(path).map_err(|err| font_error(path, err))?;
+        if data.starts_with(b"ttcf") {
+            return Err(font_error(
+                path,
+                "font collections are not supported, use a single .ttf file",
+            ));
+        }
+        let face = Face::parse(&data, 0).map_err(|err| font_error(path, err))?;
+        if face.tables().glyf.is_none() {
+            return Err(font_error(
+                path,
+                "only fonts with TrueType outlines (.ttf) can be embedded",
+            ));
+        }
+        Ok(ReplacementFont { data })
+    }
+
+    fn face(&self) -> Result<Face<'_>> {
+        Face::parse(&self.data, 0).map_err(|err| Error::UnexpectedCondition(err.to_string()))
+    }
+}
+
+/// Glyphs drawn with the font, with their characters and advances in font units
+type UsedGlyphs = BTreeMap<u16, (char, u16)>;
+
+struct TextLayout<'a> {
+    face: &'a Face<'a>,
+    used: UsedGlyphs,
+    /// Characters not found in the font, drawn with its missing glyph
+    missing: BTreeSet<char>,
+}
+
+impl<'a> TextLayout<'a> {
+    /// Glyph ids of the text and their widths in text space units (1/1000 of font size)
+    fn glyphs(&mut self, text: &str) -> Vec<(u16, f64)> {
+        let units_per_em = self.face.units_per_em() as f64;
+        text.chars()
+            .filter(|c| !c.is_control())
+            .map(|c| {
+                let glyph = self.face.glyph_index(c).unwrap_or_else(|| {
+                    self.missing.insert(c);
+                    GlyphId(0)
+                });
+                let advance = self.face.glyph_hor_advance(glyph).unwrap_or(0);
+                self.used.insert(glyph.0, (c, advance));
+                (glyph.0, advance as f64 * 1000.0 / units_per_em)
+            })
+            .collect()
+    }
+
+    /// Line height and descent, relative to font size
+    fn line_metrics(&self) -> (f64, f64) {
+        let units_per_em = self.face.units_per_em() as f64;
+        let ascender = self.face.ascender() as f64 / units_per_em;
+        let descender = self.face.descender() as f64 / units_per_em;
+        (ascender - descender, descender)
+    }
+}
+
+/// Field appearance, as set by default appearance (`DA`) of the field
+struct Appearance {
+    font_size: f64,
+    /// Operators following font selection, setting text color
+    color: String,
+    /// 0 for left, 1 for centered and 2 for right alignment
+    alignment: i64,
+    /// Number of cells of comb fields
+    comb: Option<i64>,
+}
+
+fn parse_default_appearance(da: &str) -> (f64, String) {
+    let tokens: Vec<&str> = da.split_whitespace().collect();
+    match tokens.iter().position(|token| *token == "Tf") {
+        Some(index) => (
+            index
+                .checked_sub(1)
+                .and_then(|i| tokens[i].parse().ok())
+                .unwrap_or(0.0),
+            tokens[index + 1..].join(" "),
+        ),
+        None => (0.0, da.to_string()),
+    }
+}
+
+fn hex_glyphs(glyphs: &[(u16, f64)]) -> String {
+    let hex: String = glyphs.iter().map(|(id, _)| format!("{:04X}", id)).collect();
+    format!("<{}>", hex)
+}
+
+/// Content stream drawing the text into a widget of the given size
+fn appearance_content(
+    layout: &mut TextLayout,
+    appearance: &Appearance,
+    text: &str,
+    width: f64,
+    height: f64,
+) -> Vec<u8> {
+    let glyphs = layout.glyphs(text);
+    let (line_height, descent) = layout.line_metrics();
+    let text_width: f64 = glyphs.iter().map(|(_, w)| w).sum::<f64>() / 1000.0;
+    let font_size = if appearance.font_size > 0.0 {
+        appearance.font_size
+    } else {
+        let fitting_height = (height - 2.0 * PADDING) / line_height;
+        let fitting_width = match appearance.comb {
+            Some(_) => fitting_height,
+            None if text_width > 0.0 => (width - 2.0 * PADDING) / text_width,
+            None => fitting_height,
+        };
+        fitting_height
+            .min(fitting_width)
+            .min(MAX_AUTO_FONT_SIZE)
+            .max(1.0)
+    };
+    let baseline = (height - font_size * line_height) / 2.0 - descent * font_size;
+
+    let mut text_operations = String::new();
+    match appearance.comb {
+        Some(cells) if cells > 0 => {
+            let cell_width = width / cells as f64;
+            for (index, glyph) in glyphs.iter().take(cells as usize).enumerate() {
+                let x =
+                    index as f64 * cell_width + (cell_width - glyph.1 / 1000.0 * font_size) / 2.0;
+                text_operations += &format!(
+                    "1 0 0 1 {:.2} {:.2} Tm\n{} Tj\n",
+                    x,
+                    baseline,
+                    hex_glyphs(&[*glyph])
+                );
+            }
+        }
+        _ => {
+            let scaled_width = text_width * font_size;
+            let x = match appearance.alignment {
+                1 => (width - scaled_width) / 2.0,
+                2 => width - PADDING - scaled_width,
+                _ => PADDING,
+            };
+            text_operations +=
+                &format!("{:.2} {:.2} Td\n{} Tj\n", x, baseline, hex_glyphs(&glyphs));
+        }
+    }
+    format!(
+        "/Tx BMC\nq\n1 1 {:.2} {:.2} re W n\nBT\n/{} {:.2} Tf {}\n{}ET\nQ\nEMC\n",
+        width - 2.0,
+        height - 2.0,
+        FONT_RESOURCE_NAME,
+        font_size,
+        appearance.color,
+        text_operations
+    )
+    .into_bytes()
+}
+
+/// Widget annotations of the field, which is either a widget itself or has widgets as kids
+fn widgets(field_id: ObjectId, field: &Dictionary) -> Vec<ObjectId> {
+    if field.get(b"Rect").is_ok() {
+        return vec![field_id];
+    }
+    match field.get(b"Kids") {
+        Ok(Object::Array(kids)) => kids
+            .iter()
+            .filter_map(|kid| kid.as_reference().ok())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn widget_size(document: &Document, widget_id: ObjectId) -> Option<(f64, f64)> {
+    let rect: Vec<f64> = document
+        .get_dictionary(widget_id)
+        .ok()?
+        .get(b"Rect")
+        .ok()?
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|value| value.as_f64().or_else(|_| value.as_i64().map(|v| v as f64)))
+        .collect::<std::result::Result<Vec<f64>, _>>()
+        .ok()?;
+    match rect.as_slice() {
+        [x1, y1, x2, y2] => Some(((x2 - x1).abs(), (y2 - y1).abs())),
+        _ => None,
+    }
+}
+
+fn string_value(dict: &Dictionary, key: &[u8]) -> Option<String> {
+    match dict.get(key) {
+        Ok(Object::String(value, _)) => Some(String::from_utf8_lossy(value).to_string()),
+        _ => None,
+    }
+}
+
+fn resolve_dict(document: &Document, object: Option<&Object>) -> Dictionary {
+    match object {
+        Some(Object::Dictionary(dict)) => dict.clone(),
+        Some(Object::Reference(id)) => document.get_dictionary(*id).cloned().unwrap_or_default(),
+        _ => Dictionary::new(),
+    }
+}
+
+fn to_unicode_cmap(used: &UsedGlyphs) -> Vec<u8> {
+    let entries: Vec<String> = used
+        .iter()
+        .filter(|(id, _)| **id != 0)
+        .map(|(id, (c, _))| {
+            let unicode: String = c
+                .encode_utf16(&mut [0; 2])
+                .iter()
+                .map(|unit| format!("{:04X}", unit))
+                .collect();
+            format!("<{:04X}> <{}>", id, unicode)
+        })
+        .collect();
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    // Blocks are limited to 100 entries
+    for chunk in entries.chunks(100) {
+        cmap += &format!(
+            "{} beginbfchar\n{}\nendbfchar\n",
+            chunk.len(),
+            chunk.join("\n")
+        );
+    }
+    cmap += "endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n";
+    cmap.into_bytes()
+}
+
+/// Composite font using glyph ids of the embedded TrueType font as character codes
+fn font_object(
+    document: &mut Document,
+    face: &Face,
+    font: &ReplacementFont,
+    used: &UsedGlyphs,
+) -> Result<Dictionary> {
+    let scale = 1000.0 / face.units_per_em() as f64;
+    let scaled = |value: i16| (value as f64 * scale).round() as i64;
+    let base_font: String = face
+        .names()
+        .into_iter()
+        .find(|name| name.name_id == name_id::POST_SCRIPT_NAME)
+        .and_then(|name| name.to_string())
+        .map(|name| {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect()
+        })
+        .filter(|name: &String| !name.is_empty())
+        .unwrap_or_else(|| FONT_RESOURCE_NAME.to_string());
+
+    let mut font_file = Stream::new(
+        dictionary! { "Length1" => font.data.len() as i64 },
+        font.data.clone(),
+    );
+    font_file.compress()?;
+    let font_file_id = document.add_object(font_file);
+    let bbox = face.global_bounding_box();
+    let descriptor_id = document.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => base_font.as_str(),
+        "Flags" => 32,
+        "FontBBox" => vec![
+            scaled(bbox.x_min).into(),
+            scaled(bbox.y_min).into(),
+            scaled(bbox.x_max).into(),
+            scaled(bbox.y_max).into(),
+        ],
+        "ItalicAngle" => face.italic_angle().unwrap_or(0.0) as f64,
+        "Ascent" => scaled(face.ascender()),
+        "Descent" => scaled(face.descender()),
+        "CapHeight" => scaled(face.capital_height().unwrap_or_else(|| face.ascender())),
+        "StemV" => 80,
+        "FontFile2" => font_file_id,
+    });
+    let widths: Vec<Object> = used
+        .iter()
+        .flat_map(|(id, (_, advance))| {
+            vec![
+                Object::Integer(*id as i64),
+                Object::Array(vec![Object::Integer(
+                    (*advance as f64 * scale).round() as i64
+                )]),
+            ]
+        })
+        .collect();
+    let descendant_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => base_font.as_str(),
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => descriptor_id,
+        "CIDToGIDMap" => "Identity",
+        "DW" => 1000,
+        "W" => widths,
+    });
+    let to_unicode_id = document.add_object(Stream::new(dictionary! {}, to_unicode_cmap(used)));
+    Ok(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => base_font.as_str(),
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![descendant_id.into()],
+        "ToUnicode" => to_unicode_id,
+    })
+}
+
+/// Embeds the font into the document and draws values of all text fields of the form with it.
+/// Returns characters of the values which the font has no glyphs for
+pub fn apply_font(
+    document: &mut Document,
+    pdf_form: &Form,
+    font: &ReplacementFont,
+) -> Result<BTreeSet<char>> {
+    let face = font.face()?;
+    let font_id = document.new_object_id();
+    let form_da = string_value(acroform_mut(document)?, b"DA").unwrap_or_default();
+    let mut layout = TextLayout {
+        face: &face,
+        used: UsedGlyphs::new(),
+        missing: BTreeSet::new(),
+    };
+
+    for index in 0..pdf_form.len() {
+        if !matches!(pdf_form.get_type(index), FieldType::Text) {
+            continue;
+        }
+        let field_id = pdf_form.get_object_id(index);
+        let field = document.get_dictionary(field_id)?.clone();
+        let (font_size, color) = parse_default_appearance(
+            &string_value(&field, b"DA").unwrap_or_else(|| form_da.clone()),
+        );
+        let flags = field.get(b"Ff").and_then(Object::as_i64).unwrap_or(0);
+        let appearance = Appearance {
+            font_size,
+            color: if color.is_empty() {
+                "0 g".to_string()
+            } else {
+                color
+            },
+            alignment: field.get(b"Q").and_then(Object::as_i64).unwrap_or(0),
+            comb: Some(field.get(b"MaxLen").and_then(Object::as_i64).unwrap_or(0))
+                .filter(|_| flags & COMB_FLAG != 0),
+        };
+        let value = text_value(pdf_form, index).unwrap_or_default();
+
+        for widget_id in widgets(field_id, &field) {
+            let (width, height) = match widget_size(document, widget_id) {
+                Some(size) => size,
+                None => continue,
+            };
+            let content = appearance_content(&mut layout, &appearance, &value, width, height);
+            let appearance_id = document.add_object(Stream::new(
+                dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Form",
+                    "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+                    "Resources" => dictionary! {
+                        "Font" => dictionary! { FONT_RESOURCE_NAME => font_id },
+                    },
+                },
+                content,
+            ));
+            document
+                .get_object_mut(widget_id)?
+                .as_dict_mut()?
+                .set("AP", dictionary! { "N" => appearance_id });
+        }
+        document.get_object_mut(field_id)?.as_dict_mut()?.set(
+            "DA",
+            Object::string_literal(format!(
+                "/{} {} Tf {}",
+                FONT_RESOURCE_NAME, appearance.font_size, appearance.color
+            )),
+        );
+    }
+
+    let font_dict = font_object(document, &face, font, &layout.used)?;
+    document
+        .objects
+        .insert(font_id, Object::Dictionary(font_dict));
+
+    // Font is added to form resources, so viewers can use it when editing the fields
+    let dr_object = acroform_mut(document)?.get(b"DR").ok().cloned();
+    let mut resources = resolve_dict(document, dr_object.as_ref());
+    let fonts_object = resources.get(b"Font").ok().cloned();
+    let mut fonts = resolve_dict(document, fonts_object.as_ref());
+    fonts.set(FONT_RESOURCE_NAME, font_id);
+    resources.set("Font", fonts);
+    acroform_mut(document)?.set("DR", resources);
+    Ok(layout.missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forms::formutils::{fill_field, tests::test_form};
+
+    use super::*;
+
+    /// Font with glyphs for 'A' and 'ž' only
+    fn test_font() -> ReplacementFont {
+        ReplacementFont::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/test-font.ttf"
+        ))
+        .unwrap()
+    }
+
+    fn resolve<'a>(document: &'a Document, dict: &'a Dictionary, key: &[u8]) -> &'a Object {
+        let object = dict.get(key).unwrap();
+        match object {
+            Object::Reference(id) => document.get_object(*id).unwrap(),
+            _ => object,
+        }
+    }
+
+    #[test]
+    fn parse_default_appearance_test() {
+        assert_eq!(
+            (10.0, "0 g".to_string()),
+            parse_default_appearance("/Helv 10 Tf 0 g")
+        );
+        assert_eq!(
+            (0.0, "0 0 1 rg".to_string()),
+            parse_default_appearance("/Arial 0 Tf 0 0 1 rg")
+        );
+        assert_eq!((0.0, "0 g".to_string()), parse_default_appearance("0 g"));
+    }
+
+    #[test]
+    fn apply_font_test() {
+        let mut pdf_form = test_form(&["Name", "Country"]);
+        fill_field(&mut pdf_form, 0, "AžA".to_string()).unwrap();
+        fill_field(&mut pdf_form, 1, "AB".to_string()).unwrap();
+        let mut document = pdf_form.document.clone();
+
+        let missing = apply_font(&mut document, &pdf_form, &test_font()).unwrap();
+        assert_eq!(BTreeSet::from(['B']), missing);
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        let saved = Document::load_mem(&bytes).unwrap();
+        let acroform = resolve(&saved, saved.catalog().unwrap(), b"AcroForm")
+            .as_dict()
+            .unwrap();
+        let fonts = resolve(
+            &saved,
+            acroform.get(b"DR").unwrap().as_dict().unwrap(),
+            b"Font",
+        )
+        .as_dict()
+        .unwrap();
+        let font = resolve(&saved, fonts, FONT_RESOURCE_NAME.as_bytes())
+            .as_dict()
+            .unwrap();
+        assert_eq!(b"Type0", font.get(b"Subtype").unwrap().as_name().unwrap());
+        assert_eq!(
+            b"FbihtaxTest",
+            font.get(b"BaseFont").unwrap().as_name().unwrap()
+        );
+        assert!(resolve(&saved, font, b"ToUnicode").as_stream().is_ok());
+        let descendant_id = font.get(b"DescendantFonts").unwrap().as_array().unwrap()[0]
+            .as_reference()
+            .unwrap();
+        let descendant = saved.get_dictionary(descendant_id).unwrap();
+        assert_eq!(
+            b"CIDFontType2",
+            descendant.get(b"Subtype").unwrap().as_name().unwrap()
+        );
+        let descriptor = resolve(&saved, descendant, b"FontDescriptor")
+            .as_dict()
+            .unwrap();
+        let font_file = resolve(&saved, descriptor, b"FontFile2")
+            .as_stream()
+            .unwrap();
+        assert_eq!(test_font().data, font_file.decompressed_content().unwrap());
+
+        // Missing 'B' is drawn with the missing glyph (0)
+        for (index, glyphs) in [(0, "<000100020001>"), (1, "<00010000>")] {
+            let field = saved.get_dictionary(pdf_form.get_object_id(index)).unwrap();
+            assert_eq!(
+                b"/FbihtaxFont 10 Tf 0 g".to_vec(),
+                field.get(b"DA").unwrap().as_str().unwrap()
+            );
+            let appearance = resolve(&saved, field.get(b"AP").unwrap().as_dict().unwrap(), b"N")
+                .as_stream()
+                .unwrap();
+            let content = String::from_utf8(appearance.content.clone()).unwrap();
+            assert!(content.contains("/FbihtaxFont 10.00 Tf"), "{}", content);
+            assert!(content.contains(glyphs), "{}", content);
+        }
+    }
+}
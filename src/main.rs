@@ -3,6 +3,7 @@ extern crate reqwest;
 extern crate rust_decimal;
 mod commands;
 mod config;
+mod dates;
 mod db;
 mod error;
 mod fdf;
@@ -31,6 +32,7 @@ struct CliArgs {
     config: String,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum Commands {
     #[clap(about = "Manage AMS form (income tax payment form)")]
@@ -43,7 +45,14 @@ enum Commands {
     TaxBreakdown(TaxBreakdownArgs),
 }
 
-fn main() -> error::Result<()> {
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> error::Result<()> {
     let args = CliArgs::parse();
 
     let config: Config = config::parse_config_with_default(args.config.as_str());
@@ -1,3 +1,4 @@
+use chrono::{Datelike, NaiveDate};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 
@@ -26,3 +27,19 @@ pub fn health_insurance_federation(deduced_income: Decimal) -> Decimal {
 pub fn health_insurance_canton(deduced_income: Decimal) -> Decimal {
     health_insurance_part(deduced_income) - health_insurance_federation(deduced_income)
 }
+
+pub fn personal_deduction(months: u32) -> Decimal {
+    dec!(300) * Decimal::from(months)
+}
+
+/// Day of the following month by which tax for income received in a month has to be paid
+static AMS_PAYMENT_DUE_DAY: u32 = 10;
+
+pub fn ams_payment_due_date(payment_date: NaiveDate) -> NaiveDate {
+    let (year, month) = if payment_date.month() == 12 {
+        (payment_date.year() + 1, 1)
+    } else {
+        (payment_date.year(), payment_date.month() + 1)
+    };
+    NaiveDate::from_ymd(year, month, AMS_PAYMENT_DUE_DAY)
+}